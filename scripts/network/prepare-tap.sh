@@ -6,6 +6,9 @@ set -eou pipefail
 #   * Create and start up a new tun device for berserker to use
 #   * Optionally prepare iptables for the device to be visible
 #
+# The address could be either IPv4 or IPv6 (e.g. -a fd00::1/64), for the latter
+# ip6tables and IPv6 forwarding are configured instead.
+#
 # The last step is optional, because iptables configuration could be different
 # between development environments. Meaning it's not guaranteed that this part of
 # the script is suitable for every case.
@@ -22,6 +25,8 @@ USER="$(whoami)"
 CONFIGURE_IPTABLE="false"
 CONFIGURE_FIREWALLD="false"
 CONFIGURE_TUNTAP_IF_EXISTS="false"
+IPTABLES="iptables"
+FORWARDING="net.ipv4.ip_forward"
 
 while getopts ":a:t:u:i:fo" opt; do
   case $opt in
@@ -43,6 +48,12 @@ while getopts ":a:t:u:i:fo" opt; do
   esac
 done
 
+if [[ "${ADDRESS}" == *:* ]];
+then
+    IPTABLES="ip6tables"
+    FORWARDING="net.ipv6.conf.all.forwarding"
+fi
+
 echo "Verifying if device ${NAME} is already created..."
 if ip tuntap | grep "${NAME}" &> /dev/null;
 then
@@ -60,7 +71,15 @@ ip tuntap add name "${NAME}" mode tun user "${USER}"
 ip link set "${NAME}" up
 
 echo "Assigning address ${ADDRESS} to device ${NAME}..."
-ip addr add "${ADDRESS}" dev "${NAME}"
+if [[ "${IPTABLES}" == "ip6tables" ]];
+then
+    # Skip duplicate address detection, otherwise the address is not usable
+    # for the first couple of seconds
+    sysctl "net.ipv6.conf.${NAME}.disable_ipv6=0"
+    ip -6 addr add "${ADDRESS}" dev "${NAME}" nodad
+else
+    ip addr add "${ADDRESS}" dev "${NAME}"
+fi
 
 if [[ "${CONFIGURE_FIREWALLD}" == "true" ]];
 then
@@ -72,24 +91,24 @@ fi
 
 if [[ "${CONFIGURE_IPTABLE}" == "true" ]];
 then
-    which iptables &>/dev/null || stop "Don't have the iptables tool"
+    which "${IPTABLES}" &>/dev/null || stop "Don't have the ${IPTABLES} tool"
 
     echo "Enabling ip forward..."
-    sysctl net.ipv4.ip_forward=1
+    sysctl "${FORWARDING}=1"
 
     echo "Preparing iptable..."
-    iptables -t nat -A POSTROUTING -s "${ADDRESS}" -j MASQUERADE
-    iptables -A FORWARD -i "${NAME}" -s "${ADDRESS}" -j ACCEPT
-    iptables -A FORWARD -o "${NAME}" -d "${ADDRESS}" -j ACCEPT
+    "${IPTABLES}" -t nat -A POSTROUTING -s "${ADDRESS}" -j MASQUERADE
+    "${IPTABLES}" -A FORWARD -i "${NAME}" -s "${ADDRESS}" -j ACCEPT
+    "${IPTABLES}" -A FORWARD -o "${NAME}" -d "${ADDRESS}" -j ACCEPT
 
-    RULE_NR=$(iptables -t filter -L INPUT --line-numbers |\
+    RULE_NR=$("${IPTABLES}" -t filter -L INPUT --line-numbers |\
                 grep "REJECT     all" |\
                 awk '{print $1}')
 
     # Excempt tun device from potentiall reject all rule
     if [[ $RULE_NR == "" ]]; then
-        iptables -I INPUT -i "${NAME}" -s "${ADDRESS}" -j ACCEPT
+        "${IPTABLES}" -I INPUT -i "${NAME}" -s "${ADDRESS}" -j ACCEPT
     else
-        iptables -I INPUT $((RULE_NR - 1)) -i "${NAME}" -s "${ADDRESS}" -j ACCEPT
+        "${IPTABLES}" -I INPUT $((RULE_NR - 1)) -i "${NAME}" -s "${ADDRESS}" -j ACCEPT
     fi
 fi
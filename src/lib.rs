@@ -1,9 +1,15 @@
 use core_affinity::CoreId;
+use rand::{Rng, thread_rng};
+use rand_distr::Zipf;
 use serde::{Deserialize, Deserializer};
-use std::{collections::HashMap, fmt::Display, net::Ipv4Addr, str::FromStr};
+use std::{
+    collections::HashMap, fmt::Display, net::IpAddr, path::PathBuf,
+    str::FromStr,
+};
 use syscalls::Sysno;
 
 pub mod script;
+pub mod stub;
 pub mod worker;
 
 /// Main workload configuration, contains general bits for all types of
@@ -77,24 +83,73 @@ impl ArgsMap {
     }
 }
 
+/// List of values for a single argument, separated by colons, since commas
+/// are already taken to separate arguments, e.g. "uids=0:1000:65534".
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArgsList<T>(pub Vec<T>);
+
+impl<T: FromStr> FromStr for ArgsList<T> {
+    type Err = T::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(':')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.parse())
+            .collect::<Result<Vec<_>, _>>()
+            .map(ArgsList)
+    }
+}
+
+impl FromStr for ArgsMap {
+    type Err = String;
+
+    /// Arguments in format "arg1=value1,arg2=value2".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|x| !x.is_empty())
+            .map(|arg| match arg.split_once('=') {
+                Some((key, value)) => Ok((key.to_string(), value.to_string())),
+                None => Err(format!("invalid syscall arguments format: {arg}")),
+            })
+            .collect::<Result<HashMap<_, _>, _>>()
+            .map(ArgsMap)
+    }
+}
+
 fn deserialize_args<'de, D>(deserializer: D) -> Result<ArgsMap, D::Error>
 where
     D: Deserializer<'de>,
 {
-    if let Ok(map) = String::deserialize(deserializer)?
-        .split(',')
-        .filter(|x| !x.is_empty())
-        .map(|arg| match arg.split_once('=') {
-            Some((key, value)) => Ok((key.to_string(), value.to_string())),
-            None => Err(serde::de::Error::custom(format!(
-                "invalid syscall arguments format: {arg}"
-            ))),
-        })
-        .collect::<Result<HashMap<_, _>, D::Error>>()
-    {
-        Ok(ArgsMap(map))
-    } else {
-        Err(serde::de::Error::custom("invalid syscall arguments format"))
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+
+fn deserialize_rate<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<f64>::deserialize(deserializer)? {
+        Some(rate) if !(rate > 0.0 && rate.is_finite()) => {
+            Err(serde::de::Error::custom(format!("invalid rate: {rate}")))
+        }
+        rate => Ok(rate),
+    }
+}
+
+fn deserialize_distribution<'de, D>(
+    deserializer: D,
+) -> Result<Distribution, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Distribution::deserialize(deserializer)? {
+        Distribution::Churn { lower, upper, .. } if lower > upper => {
+            Err(serde::de::Error::custom(format!(
+                "invalid port range: {lower}-{upper}"
+            )))
+        }
+        distribution => Ok(distribution),
     }
 }
 
@@ -106,8 +161,38 @@ pub enum Workload {
     /// How to listen on ports.
     Endpoints {
         /// Governing the number of ports open.
-        #[serde(flatten)]
+        #[serde(flatten, deserialize_with = "deserialize_distribution")]
         distribution: Distribution,
+
+        /// Which protocol to listen on every port with.
+        #[serde(default = "default_endpoints_protocol")]
+        protocol: Protocol,
+
+        /// Which address family to use, `dual` means a single IPv6 socket
+        /// accepting IPv4 connections as well.
+        #[serde(default = "default_endpoints_family")]
+        family: AddressFamily,
+
+        /// Addresses to bind every port to. If empty, the wildcard address
+        /// of the family is used. Addresses not matching the family are
+        /// ignored.
+        #[serde(default)]
+        bind_addrs: Vec<IpAddr>,
+
+        /// Accept incoming connections and read incoming datagrams,
+        /// otherwise they're piling up in the queues.
+        #[serde(default)]
+        accept: bool,
+
+        /// How to respond to accepted connections and incoming datagrams,
+        /// implies `accept` if anything but `close`.
+        #[serde(default = "default_endpoints_respond")]
+        respond: Responder,
+
+        /// Directory for unix socket files, a new one in the temporary
+        /// directory if not specified.
+        #[serde(default)]
+        unix_dir: Option<PathBuf>,
     },
 
     /// How to spawn processes.
@@ -120,6 +205,118 @@ pub enum Workload {
 
         /// Spawn a new process with random arguments.
         random_process: bool,
+
+        /// How to exec a new process, applies only if `random_process` is
+        /// enabled.
+        #[serde(default = "default_processes_exec_mode")]
+        exec_mode: ExecMode,
+
+        /// Depth of a process tree for every spawned process, where each
+        /// process forks descendants, e.g. shell -> interpreter -> tool. The
+        /// default is one, i.e. no descendants.
+        #[serde(default = "default_processes_tree_depth")]
+        tree_depth: ValueDistribution,
+
+        /// How many children every inner process of the tree forks.
+        #[serde(default = "default_processes_fan_out")]
+        fan_out: ValueDistribution,
+
+        /// Number of random arguments for a spawned process.
+        #[serde(default = "default_processes_args_count")]
+        args_count: ValueDistribution,
+
+        /// Length of every random argument.
+        #[serde(default = "default_processes_args_length")]
+        args_length: ValueDistribution,
+
+        /// Number of random environment variables, in addition to inherited
+        /// ones.
+        #[serde(default = "default_processes_env_count")]
+        env_count: ValueDistribution,
+
+        /// Length of every random environment variable value.
+        #[serde(default = "default_processes_env_length")]
+        env_length: ValueDistribution,
+
+        /// Command line templates to choose from instead of random
+        /// arguments, e.g. "curl -s http://{random_ip}/{random_path}". The
+        /// first word becomes argv[0]. Supported placeholders are
+        /// {random_ip}, {random_port}, {random_path} and {random_string}.
+        #[serde(default)]
+        templates: Vec<String>,
+
+        /// Names to spawn processes under. For the `path` exec mode a
+        /// symlink or a copy of the stub is created for every name, so that
+        /// it becomes the process comm. For other modes it's used only as
+        /// argv[0]. Names have to be valid file names, without `/`.
+        #[serde(default)]
+        comm_names: Vec<String>,
+
+        /// Whether to create symlinks or copies of the stub for comm names.
+        #[serde(default = "default_processes_comm_link")]
+        comm_link: CommLink,
+
+        /// Exec berserker itself in the stub mode instead of the minimal
+        /// stub. This way exec'd processes live for the sampled lifetime
+        /// and could do some activity, see `stub_open_files` and
+        /// `stub_connect`.
+        #[serde(default)]
+        builtin_stub: bool,
+
+        /// How many files every exec'd process opens, only for the built-in
+        /// stub.
+        #[serde(default)]
+        stub_open_files: u64,
+
+        /// Address in the form "host:port" every exec'd process connects to,
+        /// only for the built-in stub.
+        #[serde(default)]
+        stub_connect: Option<String>,
+
+        /// Maximum number of spawned processes alive at the same time,
+        /// unlimited if not specified. A process tree counts as one.
+        #[serde(default)]
+        max_alive: Option<u64>,
+
+        /// What to do with a new arrival when `max_alive` is reached.
+        #[serde(default = "default_processes_max_alive_policy")]
+        max_alive_policy: MaxAlivePolicy,
+    },
+
+    /// How to spawn threads inside a single process.
+    Threads {
+        /// How often a new thread will be spawn.
+        arrival_rate: f64,
+
+        /// How long threads are going to live.
+        departure_rate: f64,
+
+        /// Names to choose from for every new thread, set via
+        /// prctl(PR_SET_NAME) and truncated to 15 bytes. A placeholder {id}
+        /// is replaced with the thread sequence number, e.g.
+        /// "pool-1-thread-{id}". If empty, threads keep the process name.
+        #[serde(default)]
+        names: Vec<String>,
+
+        /// Stack size of every new thread in bytes.
+        #[serde(default = "default_threads_stack_size")]
+        stack_size: ValueDistribution,
+
+        /// How often every thread invokes a syscall during its lifetime, no
+        /// syscalls if zero.
+        #[serde(default)]
+        syscall_rate: f64,
+
+        /// Which syscall to invoke, the same as for the syscalls workload.
+        #[serde(default = "default_syscalls_syscall_nr")]
+        syscall_nr: u32,
+
+        /// Arguments for syscall in format "arg1=value1,arg2=value2"
+        #[serde(
+            deserialize_with = "deserialize_args",
+            default = "ArgsMap::new"
+        )]
+        syscall_args: ArgsMap,
     },
 
     /// How to invoke syscalls
@@ -174,13 +371,62 @@ pub enum Workload {
         server: bool,
 
         /// Which ip address to use for the server to listen on,
-        /// or for the client to connect to
-        address: Ipv4Addr,
+        /// or for the client to connect to, either IPv4 or IPv6
+        address: IpAddr,
+
+        /// Prefix length of the client network around the address. Client
+        /// addresses are allocated within this prefix, wrapping around at
+        /// the end. Defaults to 16 for IPv4 and 64 for IPv6.
+        #[serde(default)]
+        prefix_len: Option<u8>,
+
+        /// First client address, by default the one after the address. Has
+        /// to be within the prefix.
+        #[serde(default)]
+        pool_start: Option<IpAddr>,
+
+        /// How many client addresses to use, by default all of them up to the
+        /// end of the prefix. The pool is split evenly between workers.
+        #[serde(default)]
+        pool_size: Option<u64>,
+
+        /// Device to craft client traffic through.
+        #[serde(default)]
+        device: TunDevice,
+
+        /// Run the whole scenario in a new network namespace: create the
+        /// device with the address inside, and start the server side next
+        /// to the client workers. Everything is gone at exit.
+        #[serde(default)]
+        namespace: bool,
 
         /// Port for the server to listen on, or for the client
         /// to connect to.
         target_port: u16,
 
+        /// Destinations for the client to connect to instead of the address
+        /// and target_port, every flow picks one of them.
+        #[serde(default)]
+        destinations: Vec<Destination>,
+
+        /// How popular destinations are, by default according to their
+        /// weights.
+        #[serde(default)]
+        popularity: Popularity,
+
+        /// Connect to listening sockets found on the host instead, e.g. those
+        /// opened by the endpoints workload. Sockets listening on a wildcard
+        /// address are reached via the address.
+        #[serde(default)]
+        inbound: bool,
+
+        /// Which protocol to use. UDP flows are sent from spoofed addresses
+        /// the same way as TCP connections, only without a handshake, and
+        /// the server echoes datagrams back. With `both` the server handles
+        /// both, and client flows alternate between them.
+        #[serde(default = "default_network_protocol")]
+        protocol: Protocol,
+
         /// Rate of opening new connections
         arrival_rate: f64,
 
@@ -207,6 +453,57 @@ pub enum Workload {
         #[serde(default = "default_network_send_interval")]
         send_interval: u64,
 
+        /// Rate of sending messages via every connection, per second. If
+        /// specified, each connection sends on its own Poisson schedule
+        /// instead of sharing send_interval with the rest.
+        #[serde(default, deserialize_with = "deserialize_rate")]
+        send_rate: Option<f64>,
+
+        /// Size of every message in bytes, limited by buffer_size. If not
+        /// specified, a short "hello" line is sent.
+        #[serde(default)]
+        payload_size: Option<ValueDistribution>,
+
+        /// Application protocols spoken by client flows, sampled per flow.
+        /// The server detects the protocol and answers accordingly.
+        #[serde(default)]
+        application: ApplicationMix,
+
+        /// Whether the server sends all received data back instead of a short
+        /// reply per message, so that the same amount of data flows in both
+        /// directions.
+        #[serde(default)]
+        bidirectional: bool,
+
+        /// Send as much as possible via every connection, ignoring
+        /// send_interval and send_rate, to measure throughput.
+        #[serde(default)]
+        bulk: bool,
+
+        /// Size of send and receive buffers of every client socket, in bytes.
+        #[serde(default = "default_network_buffer_size")]
+        buffer_size: usize,
+
+        /// Faults to inject into the client traffic, to simulate lossy
+        /// networks and slow peers.
+        #[serde(default)]
+        faults: Faults,
+
+        /// How dynamic connections are terminated at the end of their
+        /// lifetime, sampled per connection.
+        #[serde(default)]
+        termination: TerminationMix,
+
+        /// Write all packets going through the client device into a pcap
+        /// file.
+        #[serde(default)]
+        capture: Option<Capture>,
+
+        /// Send ICMP echo requests, unreachable messages and raw IP packets
+        /// from client addresses as well.
+        #[serde(default)]
+        icmp: Option<Icmp>,
+
         /// Whether or not to wait for a connection to be removed before adding
         /// a new one, when the dynamic connection limit is reached.
         /// if true: an old connection will be forcibly removed
@@ -228,6 +525,61 @@ pub enum Workload {
     },
 }
 
+fn default_endpoints_protocol() -> Protocol {
+    Protocol::Tcp
+}
+
+fn default_endpoints_family() -> AddressFamily {
+    AddressFamily::Ipv4
+}
+
+fn default_endpoints_respond() -> Responder {
+    Responder::Close
+}
+
+fn default_processes_exec_mode() -> ExecMode {
+    ExecMode::Path
+}
+
+fn default_processes_tree_depth() -> ValueDistribution {
+    ValueDistribution::Constant { value: 1 }
+}
+
+fn default_processes_fan_out() -> ValueDistribution {
+    ValueDistribution::Constant { value: 1 }
+}
+
+fn default_processes_args_count() -> ValueDistribution {
+    ValueDistribution::Constant { value: 1 }
+}
+
+fn default_processes_args_length() -> ValueDistribution {
+    ValueDistribution::Constant { value: 7 }
+}
+
+fn default_processes_env_count() -> ValueDistribution {
+    ValueDistribution::Constant { value: 0 }
+}
+
+fn default_processes_env_length() -> ValueDistribution {
+    ValueDistribution::Constant { value: 16 }
+}
+
+fn default_processes_comm_link() -> CommLink {
+    CommLink::Symlink
+}
+
+fn default_processes_max_alive_policy() -> MaxAlivePolicy {
+    MaxAlivePolicy::Drop
+}
+
+fn default_threads_stack_size() -> ValueDistribution {
+    // The same as the default for Rust threads
+    ValueDistribution::Constant {
+        value: 2 * 1024 * 1024,
+    }
+}
+
 fn default_bpf_tracepoint() -> u64 {
     306
 }
@@ -244,6 +596,114 @@ fn default_network_send_interval() -> u64 {
     100
 }
 
+fn default_network_protocol() -> Protocol {
+    Protocol::Tcp
+}
+
+fn default_network_buffer_size() -> usize {
+    1024
+}
+
+/// How processes workload execs a new process. Every mode except `path`
+/// produces an executable from the stub payload embedded into berserker.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecMode {
+    /// Exec `stub` binary found in PATH.
+    Path,
+
+    /// Exec an anonymous memory file via execveat, without touching any
+    /// filesystem.
+    Memfd,
+
+    /// Exec a file placed in /dev/shm.
+    Shm,
+
+    /// Exec an already unlinked file via execveat on its file descriptor.
+    Deleted,
+
+    /// Exec a file via /proc/self/fd/N path.
+    ProcFd,
+
+    /// Exec a script with a shebang, pointing to the stub as interpreter.
+    Script,
+}
+
+/// How to produce an executable with a different name.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommLink {
+    Symlink,
+    Copy,
+}
+
+/// What to do with a new process, when too many are already alive.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MaxAlivePolicy {
+    /// Do not spawn the process, only count it as dropped.
+    Drop,
+
+    /// Wait until one of alive processes exits.
+    Wait,
+
+    /// Kill the oldest alive process to make room for the new one.
+    KillOldest,
+}
+
+/// Transport protocol for listening sockets and network flows.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+    Both,
+}
+
+/// Address family for listening sockets.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    Ipv4,
+    Ipv6,
+    /// IPv6 sockets without IPV6_V6ONLY, so that IPv4 is accepted as well.
+    Dual,
+
+    /// Unix sockets, with a file for every port in `unix_dir`.
+    Unix,
+
+    /// Unix sockets in the abstract namespace, named after the port.
+    Abstract,
+}
+
+/// Protocol to serve accepted connections with.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(tag = "protocol", rename_all = "lowercase")]
+pub enum Responder {
+    /// Close connections right away, drop datagrams.
+    Close,
+
+    /// Send back everything received.
+    Echo,
+
+    /// Send a fixed banner and close, e.g. "SSH-2.0-OpenSSH_9.6\r\n".
+    Banner { banner: String },
+
+    /// Read an HTTP request and reply with a fixed response. Datagrams are
+    /// dropped.
+    Http {
+        #[serde(default = "default_http_status")]
+        status: u16,
+
+        #[serde(default)]
+        body: String,
+    },
+}
+
+fn default_http_status() -> u16 {
+    200
+}
+
 /// Distribution for number of ports to listen on
 #[derive(Debug, Copy, Clone, Deserialize)]
 #[serde(tag = "distribution")]
@@ -255,6 +715,304 @@ pub enum Distribution {
     /// Every process opens more or less the same number of ports.
     #[serde(alias = "uniform")]
     Uniform { lower: u64, upper: u64 },
+
+    /// Every process opens and closes ports over time, picking them randomly
+    /// from the range between lower and upper (inclusive).
+    #[serde(alias = "churn")]
+    Churn {
+        /// How often a new port is opened.
+        arrival_rate: f64,
+
+        /// How long a port stays open.
+        departure_rate: f64,
+
+        lower: u16,
+        upper: u16,
+    },
+}
+
+/// Distribution of a generic sampled value, e.g. depth of a process tree.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(tag = "distribution", rename_all = "lowercase")]
+pub enum ValueDistribution {
+    /// Always the same value.
+    Constant { value: u64 },
+
+    /// Every value between lower and upper (inclusive) is equally likely.
+    Uniform { lower: u64, upper: u64 },
+
+    /// Values between 1 and n, small values are much more frequent than
+    /// large ones.
+    #[serde(alias = "zipfian")]
+    Zipf { n: u64, exponent: f64 },
+}
+
+impl ValueDistribution {
+    pub fn sample(&self) -> u64 {
+        match *self {
+            ValueDistribution::Constant { value } => value,
+            ValueDistribution::Uniform { lower, upper } => {
+                thread_rng().gen_range(lower..=upper.max(lower))
+            }
+            ValueDistribution::Zipf { n, exponent } => {
+                let value: f64 =
+                    thread_rng().sample(Zipf::new(n, exponent).unwrap());
+                value as u64
+            }
+        }
+    }
+}
+
+/// Destination of network client flows.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Destination {
+    pub address: IpAddr,
+    pub port: u16,
+
+    /// Relative popularity of the destination.
+    #[serde(default = "default_destination_weight")]
+    pub weight: f64,
+}
+
+fn default_destination_weight() -> f64 {
+    1.0
+}
+
+/// How flows are distributed across destinations.
+#[derive(Debug, Copy, Clone, Default, Deserialize, PartialEq)]
+#[serde(tag = "distribution", rename_all = "lowercase")]
+pub enum Popularity {
+    /// Proportionally to destination weights, found destinations are equally
+    /// popular.
+    #[default]
+    Weighted,
+
+    /// The first destinations are much more popular than the rest, weights
+    /// are ignored.
+    #[serde(alias = "zipfian")]
+    Zipf { exponent: f64 },
+}
+
+/// Device the network client crafts its traffic through. It has to be
+/// configured with the address and routes beforehand.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TunDevice {
+    /// Name of the device, or a prefix of it if every worker uses its own.
+    pub name: String,
+
+    pub medium: DeviceMedium,
+
+    pub sharing: Sharing,
+}
+
+impl Default for TunDevice {
+    fn default() -> Self {
+        TunDevice {
+            name: String::from("berserker0"),
+            medium: DeviceMedium::Tun,
+            sharing: Sharing::Shared,
+        }
+    }
+}
+
+/// What kind of packets go through the device.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceMedium {
+    /// IP packets.
+    Tun,
+
+    /// Ethernet frames, the client uses a generated MAC address.
+    Tap,
+}
+
+/// How multiple workers use the device.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Sharing {
+    /// All workers attach to the same device, each to its own queue. The
+    /// device has to be created with multi_queue if there is more than one
+    /// worker.
+    Shared,
+
+    /// Every worker opens its own device, named after the worker index,
+    /// e.g. berserker0-1.
+    PerWorker,
+}
+
+/// Faults injected by the network client into packets going through the tun
+/// device, everything is disabled by default.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Faults {
+    /// Probability of dropping a packet, in percents.
+    pub drop_chance: u8,
+
+    /// Probability of corrupting a packet with a single bit flip, in
+    /// percents.
+    pub corrupt_chance: u8,
+
+    /// Packets larger than that are dropped, in bytes. Zero means no limit.
+    pub max_packet_size: usize,
+
+    /// How many packets could be sent per bucket interval. Zero means no
+    /// limit.
+    pub max_tx_rate: u64,
+
+    /// How many packets could be received per bucket interval. Zero means no
+    /// limit.
+    pub max_rx_rate: u64,
+
+    /// Interval to refill rate limiting buckets, in milliseconds.
+    pub bucket_interval: u64,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Faults {
+            drop_chance: 0,
+            corrupt_chance: 0,
+            max_packet_size: 0,
+            max_tx_rate: 0,
+            max_rx_rate: 0,
+            bucket_interval: 1000,
+        }
+    }
+}
+
+/// Weights of application protocols of network client flows, by default
+/// only "hello" lines are sent. Protocols not available for the transport of
+/// a flow are skipped, e.g. DNS for TCP flows.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ApplicationMix {
+    /// "hello" lines, or lines of payload_size. TCP and UDP.
+    pub hello: f64,
+
+    /// HTTP/1.1 requests with random paths and headers, POST with a body of
+    /// payload_size if specified. TCP only.
+    pub http: f64,
+
+    /// DNS queries for random names. UDP only.
+    pub dns: f64,
+
+    /// Binary frames prefixed with a flag byte and a 32 bit length, like
+    /// gRPC messages, with payload_size bytes of payload. TCP and UDP.
+    pub frames: f64,
+}
+
+impl Default for ApplicationMix {
+    fn default() -> Self {
+        ApplicationMix {
+            hello: 1.0,
+            http: 0.0,
+            dns: 0.0,
+            frames: 0.0,
+        }
+    }
+}
+
+/// Packet capture of the network client traffic.
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Capture {
+    /// Path of the pcap file. With multiple workers every one of them writes
+    /// its own file with the worker index added to the name, e.g.
+    /// capture-1.pcap.
+    pub path: PathBuf,
+
+    /// Maximum number of bytes stored per packet.
+    #[serde(default = "default_capture_snaplen")]
+    pub snaplen: u32,
+
+    /// Start a new file once the current one exceeds the size in bytes,
+    /// moving older ones to path.1, path.2 and so on.
+    #[serde(default)]
+    pub rotate_size: Option<u64>,
+
+    /// How many older files to keep when rotating.
+    #[serde(default = "default_capture_rotate_count")]
+    pub rotate_count: usize,
+}
+
+fn default_capture_snaplen() -> u32 {
+    65535
+}
+
+fn default_capture_rotate_count() -> usize {
+    10
+}
+
+/// ICMP and raw IP traffic of the network client, sent from spoofed client
+/// addresses next to regular flows. Every kind of traffic is sent according
+/// to its own Poisson process, only echo requests are sent by default.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct Icmp {
+    /// Number of client addresses sending echo requests, every one with its
+    /// own echo identifier. Unreachable messages and raw packets are sent
+    /// from the same addresses.
+    pub flows: u32,
+
+    /// Echo requests per second.
+    pub echo_rate: f64,
+
+    /// Destination unreachable messages per second, reporting a made up UDP
+    /// datagram from the destination to the client as undeliverable.
+    pub unreachable_rate: f64,
+
+    /// Raw IP packets per second.
+    pub raw_rate: f64,
+
+    /// IP protocol number of raw packets, 253 reserved for experimentation
+    /// by default.
+    pub raw_protocol: u8,
+
+    /// Size of echo data and raw packet payload in bytes.
+    pub payload_size: usize,
+}
+
+impl Default for Icmp {
+    fn default() -> Self {
+        Icmp {
+            flows: 1,
+            echo_rate: 1.0,
+            unreachable_rate: 0.0,
+            raw_rate: 0.0,
+            raw_protocol: 253,
+            payload_size: 56,
+        }
+    }
+}
+
+/// Weights of ways to terminate a dynamic network connection, by default
+/// connections are closed gracefully.
+#[derive(Debug, Copy, Clone, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct TerminationMix {
+    /// Close the connection with FIN.
+    pub graceful: f64,
+
+    /// Abort the connection with RST.
+    pub abort: f64,
+
+    /// Disappear without telling the peer, leaving the connection half-open.
+    pub silent: f64,
+
+    /// Never complete the handshake, as if the server dropped SYN. Such
+    /// connections disappear at the end of their lifetime.
+    pub handshake_timeout: f64,
+}
+
+impl Default for TerminationMix {
+    fn default() -> Self {
+        TerminationMix {
+            graceful: 1.0,
+            abort: 0.0,
+            silent: 0.0,
+            handshake_timeout: 0.0,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -296,7 +1054,7 @@ impl Display for BaseConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use config::{Config, File, FileFormat};
+    use config::{Config, ConfigError, File, FileFormat};
 
     #[test]
     fn test_processes() {
@@ -327,26 +1085,34 @@ mod tests {
             arrival_rate,
             departure_rate,
             random_process,
+            exec_mode,
+            tree_depth,
+            fan_out,
+            ..
         } = workload
         {
             assert_eq!(arrival_rate, 10.0);
             assert_eq!(departure_rate, 200.0);
             assert!(random_process);
+            assert_eq!(exec_mode, ExecMode::Path);
+            assert_eq!(tree_depth, ValueDistribution::Constant { value: 1 });
+            assert_eq!(fan_out, ValueDistribution::Constant { value: 1 });
         } else {
             panic!("wrong workload type found");
         }
     }
 
     #[test]
-    fn test_endpoints_zipf() {
+    fn test_processes_exec_mode() {
         let input = r#"
             restart_interval = 10
 
             [workload]
-            type = "endpoints"
-            distribution = "zipf"
-            n_ports = 200
-            exponent = 1.4
+            type = "processes"
+            arrival_rate = 10.0
+            departure_rate = 200.0
+            random_process = true
+            exec_mode = "proc_fd"
         "#;
 
         let config = Config::builder()
@@ -356,35 +1122,73 @@ mod tests {
             .try_deserialize::<WorkloadConfig>()
             .expect("failed to deserialize into WorkloadConfig");
 
-        let WorkloadConfig {
-            restart_interval,
-            workload,
-            ..
-        } = config;
-        assert_eq!(restart_interval, 10);
+        if let Workload::Processes { exec_mode, .. } = config.workload {
+            assert_eq!(exec_mode, ExecMode::ProcFd);
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
 
-        if let Workload::Endpoints { distribution, .. } = workload {
-            if let Distribution::Zipfian { n_ports, exponent } = distribution {
-                assert_eq!(n_ports, 200);
-                assert_eq!(exponent, 1.4);
-            } else {
-                panic!("wrong distribution type found");
-            }
+    #[test]
+    fn test_processes_tree() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "processes"
+            arrival_rate = 10.0
+            departure_rate = 200.0
+            random_process = false
+            tree_depth = { distribution = "uniform", lower = 2, upper = 5 }
+            fan_out = { distribution = "zipf", n = 10, exponent = 1.5 }
+        "#;
+
+        let config = Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig");
+
+        if let Workload::Processes {
+            tree_depth,
+            fan_out,
+            ..
+        } = config.workload
+        {
+            assert_eq!(
+                tree_depth,
+                ValueDistribution::Uniform { lower: 2, upper: 5 }
+            );
+            assert_eq!(
+                fan_out,
+                ValueDistribution::Zipf {
+                    n: 10,
+                    exponent: 1.5
+                }
+            );
+            assert!((2..=5).contains(&tree_depth.sample()));
+            assert!((1..=10).contains(&fan_out.sample()));
         } else {
             panic!("wrong workload type found");
         }
     }
 
     #[test]
-    fn test_endpoints_uniform() {
+    fn test_processes_command_line() {
         let input = r#"
             restart_interval = 10
 
             [workload]
-            type = "endpoints"
-            distribution = "uniform"
-            upper = 100
-            lower = 1
+            type = "processes"
+            arrival_rate = 10.0
+            departure_rate = 200.0
+            random_process = true
+            args_count = { distribution = "uniform", lower = 1, upper = 10 }
+            env_count = { distribution = "constant", value = 5 }
+            templates = ["curl -s http://{random_ip}/{random_path}"]
+            comm_names = ["curl", "python3"]
+            comm_link = "copy"
         "#;
 
         let config = Config::builder()
@@ -394,20 +1198,727 @@ mod tests {
             .try_deserialize::<WorkloadConfig>()
             .expect("failed to deserialize into WorkloadConfig");
 
-        let WorkloadConfig {
-            restart_interval,
-            workload,
+        if let Workload::Processes {
+            args_count,
+            args_length,
+            env_count,
+            templates,
+            comm_names,
+            comm_link,
             ..
-        } = config;
-        assert_eq!(restart_interval, 10);
+        } = config.workload
+        {
+            assert_eq!(
+                args_count,
+                ValueDistribution::Uniform {
+                    lower: 1,
+                    upper: 10
+                }
+            );
+            assert_eq!(args_length, ValueDistribution::Constant { value: 7 });
+            assert_eq!(env_count, ValueDistribution::Constant { value: 5 });
+            assert_eq!(
+                templates,
+                vec!["curl -s http://{random_ip}/{random_path}"]
+            );
+            assert_eq!(comm_names, vec!["curl", "python3"]);
+            assert_eq!(comm_link, CommLink::Copy);
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
 
-        if let Workload::Endpoints { distribution } = workload {
-            if let Distribution::Uniform { lower, upper } = distribution {
-                assert_eq!(lower, 1);
-                assert_eq!(upper, 100);
-            } else {
-                panic!("wrong distribution type found");
-            }
+    #[test]
+    fn test_processes_max_alive() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "processes"
+            arrival_rate = 10.0
+            departure_rate = 200.0
+            random_process = false
+            max_alive = 100
+            max_alive_policy = "kill_oldest"
+        "#;
+
+        let config = Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig");
+
+        if let Workload::Processes {
+            max_alive,
+            max_alive_policy,
+            ..
+        } = config.workload
+        {
+            assert_eq!(max_alive, Some(100));
+            assert_eq!(max_alive_policy, MaxAlivePolicy::KillOldest);
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_threads() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "threads"
+            arrival_rate = 100.0
+            departure_rate = 10.0
+            names = ["pool-1-thread-{id}", "GC Thread"]
+            stack_size = { distribution = "uniform", lower = 65536, upper = 1048576 }
+            syscall_rate = 50.0
+            syscall_nr = 39
+        "#;
+
+        let config = Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig");
+
+        if let Workload::Threads {
+            arrival_rate,
+            departure_rate,
+            names,
+            stack_size,
+            syscall_rate,
+            syscall_nr,
+            ..
+        } = config.workload
+        {
+            assert_eq!(arrival_rate, 100.0);
+            assert_eq!(departure_rate, 10.0);
+            assert_eq!(names, vec!["pool-1-thread-{id}", "GC Thread"]);
+            assert_eq!(
+                stack_size,
+                ValueDistribution::Uniform {
+                    lower: 65536,
+                    upper: 1048576
+                }
+            );
+            assert_eq!(syscall_rate, 50.0);
+            assert_eq!(syscall_nr, 39);
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_endpoints_zipf() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "endpoints"
+            distribution = "zipf"
+            n_ports = 200
+            exponent = 1.4
+        "#;
+
+        let config = Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig");
+
+        let WorkloadConfig {
+            restart_interval,
+            workload,
+            ..
+        } = config;
+        assert_eq!(restart_interval, 10);
+
+        if let Workload::Endpoints { distribution, .. } = workload {
+            if let Distribution::Zipfian { n_ports, exponent } = distribution {
+                assert_eq!(n_ports, 200);
+                assert_eq!(exponent, 1.4);
+            } else {
+                panic!("wrong distribution type found");
+            }
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_endpoints_uniform() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "endpoints"
+            distribution = "uniform"
+            upper = 100
+            lower = 1
+        "#;
+
+        let config = Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig");
+
+        let WorkloadConfig {
+            restart_interval,
+            workload,
+            ..
+        } = config;
+        assert_eq!(restart_interval, 10);
+
+        if let Workload::Endpoints { distribution, .. } = workload {
+            if let Distribution::Uniform { lower, upper } = distribution {
+                assert_eq!(lower, 1);
+                assert_eq!(upper, 100);
+            } else {
+                panic!("wrong distribution type found");
+            }
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_endpoints_churn() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "endpoints"
+            distribution = "churn"
+            arrival_rate = 10.0
+            departure_rate = 0.5
+            lower = 10000
+            upper = 20000
+        "#;
+
+        let config = Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig");
+
+        if let Workload::Endpoints { distribution, .. } = config.workload {
+            if let Distribution::Churn {
+                arrival_rate,
+                departure_rate,
+                lower,
+                upper,
+            } = distribution
+            {
+                assert_eq!(arrival_rate, 10.0);
+                assert_eq!(departure_rate, 0.5);
+                assert_eq!(lower, 10000);
+                assert_eq!(upper, 20000);
+            } else {
+                panic!("wrong distribution type found");
+            }
+        } else {
+            panic!("wrong workload type found");
+        }
+
+        // The range is inclusive, but can't be empty
+        let input = input.replace("upper = 20000", "upper = 9999");
+        let error = Config::builder()
+            .add_source(File::from_str(&input, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize::<WorkloadConfig>()
+            .unwrap_err();
+        assert!(error.to_string().contains("invalid port range"), "{error}");
+    }
+
+    #[test]
+    fn test_endpoints_unix() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "endpoints"
+            distribution = "zipf"
+            n_ports = 200
+            exponent = 1.4
+            protocol = "udp"
+            family = "unix"
+            unix_dir = "/run/berserker"
+        "#;
+
+        let config = Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig");
+
+        if let Workload::Endpoints {
+            protocol,
+            family,
+            unix_dir,
+            ..
+        } = config.workload
+        {
+            assert_eq!(protocol, Protocol::Udp);
+            assert_eq!(family, AddressFamily::Unix);
+            assert_eq!(unix_dir, Some(PathBuf::from("/run/berserker")));
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_endpoints_protocol() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "endpoints"
+            distribution = "uniform"
+            upper = 100
+            lower = 1
+            protocol = "both"
+            family = "dual"
+            bind_addrs = ["::1", "127.0.0.1"]
+            accept = true
+            respond = { protocol = "http", body = "hello" }
+        "#;
+
+        let config = Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig");
+
+        if let Workload::Endpoints {
+            protocol,
+            family,
+            bind_addrs,
+            accept,
+            respond,
+            ..
+        } = config.workload
+        {
+            assert!(accept);
+            assert_eq!(
+                respond,
+                Responder::Http {
+                    status: 200,
+                    body: String::from("hello")
+                }
+            );
+            assert_eq!(protocol, Protocol::Both);
+            assert_eq!(family, AddressFamily::Dual);
+            assert_eq!(
+                bind_addrs,
+                vec![
+                    "::1".parse::<IpAddr>().unwrap(),
+                    "127.0.0.1".parse::<IpAddr>().unwrap()
+                ]
+            );
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    /// Client side of the network workload with the mandatory options, and
+    /// extra ones to test.
+    fn parse_network(extra: &str) -> Result<WorkloadConfig, ConfigError> {
+        let input = format!(
+            r#"
+            restart_interval = 10
+
+            [workload]
+            type = "network"
+            server = false
+            address = "10.0.0.1"
+            target_port = 8080
+            arrival_rate = 0.1
+            departure_rate = 0.1
+            connections_static = 10
+            connections_dyn_max = 100
+            preempt = true
+            {extra}
+        "#
+        );
+
+        Config::builder()
+            .add_source(File::from_str(&input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+    }
+
+    fn network(extra: &str) -> Workload {
+        parse_network(extra)
+            .expect("failed to deserialize into WorkloadConfig")
+            .workload
+    }
+
+    #[test]
+    fn test_network_ipv6() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "network"
+            server = false
+            address = "fd00::1"
+            prefix_len = 112
+            pool_start = "fd00::100"
+            pool_size = 64
+            target_port = 8080
+            arrival_rate = 0.1
+            departure_rate = 0.1
+            connections_static = 10
+            connections_dyn_max = 100
+            preempt = true
+        "#;
+
+        let workload = Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig")
+            .workload;
+
+        if let Workload::Network {
+            address,
+            prefix_len,
+            pool_start,
+            pool_size,
+            ..
+        } = workload
+        {
+            assert_eq!(address, "fd00::1".parse::<IpAddr>().unwrap());
+            assert_eq!(prefix_len, Some(112));
+            assert_eq!(pool_start, Some("fd00::100".parse().unwrap()));
+            assert_eq!(pool_size, Some(64));
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_network_udp() {
+        if let Workload::Network { protocol, .. } = network("") {
+            assert_eq!(protocol, Protocol::Tcp);
+        } else {
+            panic!("wrong workload type found");
+        }
+
+        if let Workload::Network { protocol, .. } =
+            network(r#"protocol = "udp""#)
+        {
+            assert_eq!(protocol, Protocol::Udp);
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_network_payload() {
+        let workload = network(
+            r#"
+            send_rate = 10.0
+            payload_size = { distribution = "uniform", lower = 64, upper = 1024 }
+            bidirectional = true
+            buffer_size = 65536
+        "#,
+        );
+
+        if let Workload::Network {
+            send_rate,
+            payload_size,
+            bidirectional,
+            bulk,
+            buffer_size,
+            ..
+        } = workload
+        {
+            assert_eq!(send_rate, Some(10.0));
+            assert_eq!(
+                payload_size,
+                Some(ValueDistribution::Uniform {
+                    lower: 64,
+                    upper: 1024
+                })
+            );
+            assert!(bidirectional);
+            assert!(!bulk);
+            assert_eq!(buffer_size, 65536);
+        } else {
+            panic!("wrong workload type found");
+        }
+
+        if let Workload::Network { bulk, .. } = network("bulk = true") {
+            assert!(bulk);
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_network_send_rate() {
+        for send_rate in ["0.0", "-1.0", "inf", "nan"] {
+            assert!(
+                parse_network(&format!("send_rate = {send_rate}")).is_err(),
+                "{send_rate} is accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn test_network_faults() {
+        let workload = network(
+            r#"
+            [workload.faults]
+            drop_chance = 5
+            max_tx_rate = 100
+        "#,
+        );
+
+        if let Workload::Network { faults, .. } = workload {
+            assert_eq!(
+                faults,
+                Faults {
+                    drop_chance: 5,
+                    max_tx_rate: 100,
+                    ..Faults::default()
+                }
+            );
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_network_termination() {
+        let workload = network(
+            r#"
+            [workload.termination]
+            abort = 1.0
+            handshake_timeout = 0.5
+        "#,
+        );
+
+        if let Workload::Network { termination, .. } = workload {
+            assert_eq!(
+                termination,
+                TerminationMix {
+                    graceful: 1.0,
+                    abort: 1.0,
+                    silent: 0.0,
+                    handshake_timeout: 0.5,
+                }
+            );
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_network_destinations() {
+        let workload = network(
+            r#"
+            destinations = [
+                { address = "fd00::1", port = 80, weight = 10.0 },
+                { address = "fd00::2", port = 443 },
+            ]
+            popularity = { distribution = "zipf", exponent = 1.5 }
+        "#,
+        );
+
+        if let Workload::Network {
+            ref destinations,
+            popularity,
+            inbound,
+            ..
+        } = workload
+        {
+            assert_eq!(
+                destinations,
+                &vec![
+                    Destination {
+                        address: "fd00::1".parse().unwrap(),
+                        port: 80,
+                        weight: 10.0,
+                    },
+                    Destination {
+                        address: "fd00::2".parse().unwrap(),
+                        port: 443,
+                        weight: 1.0,
+                    },
+                ]
+            );
+            assert_eq!(popularity, Popularity::Zipf { exponent: 1.5 });
+            assert!(!inbound);
+        } else {
+            panic!("wrong workload type found");
+        }
+
+        if let Workload::Network { inbound, .. } = network("inbound = true") {
+            assert!(inbound);
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_network_device() {
+        let workload = network(
+            r#"
+            [workload.device]
+            name = "client0"
+            medium = "tap"
+            sharing = "per_worker"
+        "#,
+        );
+
+        if let Workload::Network { ref device, .. } = workload {
+            assert_eq!(
+                device,
+                &TunDevice {
+                    name: String::from("client0"),
+                    medium: DeviceMedium::Tap,
+                    sharing: Sharing::PerWorker,
+                }
+            );
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_network_namespace() {
+        if let Workload::Network { namespace, .. } = network("") {
+            assert!(!namespace);
+        } else {
+            panic!("wrong workload type found");
+        }
+
+        if let Workload::Network { namespace, .. } = network("namespace = true")
+        {
+            assert!(namespace);
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_network_capture() {
+        let workload = network(
+            r#"
+            [workload.capture]
+            path = "/tmp/berserker.pcap"
+            rotate_size = 1048576
+        "#,
+        );
+
+        if let Workload::Network { ref capture, .. } = workload {
+            assert_eq!(
+                capture,
+                &Some(Capture {
+                    path: PathBuf::from("/tmp/berserker.pcap"),
+                    snaplen: 65535,
+                    rotate_size: Some(1048576),
+                    rotate_count: 10,
+                })
+            );
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_network_application() {
+        let workload = network(
+            r#"
+            [workload.application]
+            http = 2.0
+            frames = 0.5
+        "#,
+        );
+
+        if let Workload::Network { application, .. } = workload {
+            assert_eq!(
+                application,
+                ApplicationMix {
+                    hello: 1.0,
+                    http: 2.0,
+                    dns: 0.0,
+                    frames: 0.5,
+                }
+            );
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_network_icmp() {
+        let workload = network(
+            r#"
+            [workload.icmp]
+            flows = 4
+            unreachable_rate = 0.5
+            raw_protocol = 143
+        "#,
+        );
+
+        if let Workload::Network { icmp, .. } = workload {
+            assert_eq!(
+                icmp,
+                Some(Icmp {
+                    flows: 4,
+                    unreachable_rate: 0.5,
+                    raw_protocol: 143,
+                    ..Icmp::default()
+                })
+            );
+        } else {
+            panic!("wrong workload type found");
+        }
+    }
+
+    #[test]
+    fn test_syscalls_args_list() {
+        let input = r#"
+            restart_interval = 10
+
+            [workload]
+            type = "syscalls"
+            syscall_nr = 117
+            syscall_args = "churn=true,uids=0:1000:65534,groups="
+        "#;
+
+        let config = Config::builder()
+            .add_source(File::from_str(input, FileFormat::Toml))
+            .build()
+            .expect("failed to parse configuration")
+            .try_deserialize::<WorkloadConfig>()
+            .expect("failed to deserialize into WorkloadConfig");
+
+        if let Workload::Syscalls { syscall_args, .. } = config.workload {
+            assert!(syscall_args.get("churn", false));
+            assert_eq!(
+                syscall_args.get("uids", ArgsList::<u32>(vec![])),
+                ArgsList(vec![0, 1000, 65534])
+            );
+            assert_eq!(
+                syscall_args.get("groups", ArgsList(vec![1u32])),
+                ArgsList(vec![])
+            );
         } else {
             panic!("wrong workload type found");
         }
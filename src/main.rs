@@ -25,12 +25,35 @@ use nix::sys::signal::{Signal, kill};
 use nix::sys::wait::waitpid;
 use nix::unistd::Pid;
 use std::time::SystemTime;
-use std::{env, thread, time};
+use std::{env, process, thread, time};
 
-use berserker::{WorkloadConfig, worker::new_worker};
+use berserker::{
+    Worker, WorkloadConfig,
+    stub::{self, StubOptions},
+    worker::{new_worker, prepare},
+};
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+
+    // Built-in stub mode, serves as an exec target for processes workload
+    if args.get(1).is_some_and(|arg| arg == "stub") {
+        match StubOptions::parse(&args[2..]) {
+            Ok(options) => {
+                if let Err(e) = stub::run(&options) {
+                    eprintln!("Stub failed: {e}");
+                    process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("Invalid stub arguments: {e}");
+                process::exit(1);
+            }
+        }
+
+        return;
+    }
+
     let default_config = String::from("workload.toml");
     let config_path = &args.get(1).unwrap_or(&default_config);
     let duration_timer = SystemTime::now();
@@ -68,8 +91,21 @@ fn main() {
         vec![CoreId { id: 0 }]
     };
 
-    let handles: Vec<_> = iproduct!(core_ids.into_iter(), 0..config.workers)
-        .map(|(cpu, process)| {
+    // Workers needed by the workload itself, e.g. a server side, go first
+    let prepared = match prepare(&config) {
+        Ok(workers) => workers,
+        Err(e) => {
+            error!("Failed to prepare the workload, {e}");
+            process::exit(1);
+        }
+    };
+    let mut handles: Vec<_> = prepared
+        .into_iter()
+        .map(|worker| spawn(worker, None))
+        .collect();
+
+    handles.extend(iproduct!(core_ids.into_iter(), 0..config.workers).map(
+        |(cpu, process)| {
             let worker = new_worker(
                 config.clone(),
                 cpu,
@@ -78,27 +114,9 @@ fn main() {
                 &mut upper,
             );
 
-            match fork() {
-                Ok(Fork::Parent(child)) => {
-                    info!("Child {}", child);
-                    Some(child)
-                }
-                Ok(Fork::Child) => {
-                    if config.per_core {
-                        core_affinity::set_for_current(cpu);
-                    }
-
-                    loop {
-                        worker.run_payload().unwrap();
-                    }
-                }
-                Err(e) => {
-                    warn!("Failed: {e:?}");
-                    None
-                }
-            }
-        })
-        .collect();
+            spawn(worker, config.per_core.then_some(cpu))
+        },
+    ));
 
     info!("In total: {}", upper);
 
@@ -133,3 +151,27 @@ fn main() {
         });
     });
 }
+
+/// Fork a process running the worker payload, pinned to the cpu if
+/// specified.
+fn spawn(worker: Box<dyn Worker>, cpu: Option<CoreId>) -> Option<i32> {
+    match fork() {
+        Ok(Fork::Parent(child)) => {
+            info!("Child {}", child);
+            Some(child)
+        }
+        Ok(Fork::Child) => {
+            if let Some(cpu) = cpu {
+                core_affinity::set_for_current(cpu);
+            }
+
+            loop {
+                worker.run_payload().unwrap();
+            }
+        }
+        Err(e) => {
+            warn!("Failed: {e:?}");
+            None
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Built-in stub mode, an exec target for the processes workload. In
+//! contrast with the minimal stub binary it could live for a specified time
+//! and do some file/network activity, that will be attributed to the process.
+//!
+//! Invoked as `berserker stub [--lifetime-ms N] [--open-files K]
+//! [--connect host:port] [--tree F,...] [-- args...]`. Unknown positional
+//! arguments are ignored, everything after `--` is ignored as well.
+
+use std::{
+    env,
+    ffi::{CStr, OsStr, OsString},
+    fs,
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::{ffi::OsStrExt, process::CommandExt},
+    path::PathBuf,
+    process::{self, Command},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StubOptions {
+    /// For how long the process is going to live.
+    pub lifetime_ms: u64,
+
+    /// How many files to create and open.
+    pub open_files: u64,
+
+    /// Address to open a TCP connection to.
+    pub connect: Option<String>,
+
+    /// Fan-outs of the process tree rooted at this process, in pre-order.
+    /// Children are spawned by exec'ing the stub again with their subtrees,
+    /// a leaf has nothing to spawn.
+    pub tree: Vec<u64>,
+}
+
+impl StubOptions {
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = StubOptions::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .cloned()
+                    .ok_or(format!("missing value for {name}"))
+            };
+
+            match arg.as_str() {
+                "--lifetime-ms" => {
+                    options.lifetime_ms = value(arg)?
+                        .parse()
+                        .map_err(|e| format!("invalid lifetime: {e}"))?;
+                }
+                "--open-files" => {
+                    options.open_files = value(arg)?
+                        .parse()
+                        .map_err(|e| format!("invalid open files: {e}"))?;
+                }
+                "--connect" => options.connect = Some(value(arg)?),
+                "--tree" => {
+                    options.tree = value(arg)?
+                        .split(',')
+                        .map(|fan_out| fan_out.parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|e| format!("invalid tree: {e}"))?;
+
+                    if subtree_len(&options.tree) != Some(options.tree.len()) {
+                        return Err(String::from("invalid tree: malformed"));
+                    }
+                }
+                "--" => break,
+                _ => {}
+            }
+        }
+
+        Ok(options)
+    }
+
+    /// Arguments to pass to the stub to get the same options back.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![
+            String::from("stub"),
+            String::from("--lifetime-ms"),
+            self.lifetime_ms.to_string(),
+            String::from("--open-files"),
+            self.open_files.to_string(),
+        ];
+
+        if let Some(connect) = &self.connect {
+            args.push(String::from("--connect"));
+            args.push(connect.clone());
+        }
+
+        if self.tree.len() > 1 {
+            let tree: Vec<_> = self.tree.iter().map(u64::to_string).collect();
+            args.push(String::from("--tree"));
+            args.push(tree.join(","));
+        }
+
+        args.push(String::from("--"));
+        args
+    }
+}
+
+/// Subtrees of the children of the tree root, in the same pre-order form.
+/// The tree has to be well-formed.
+pub fn children(tree: &[u64]) -> Vec<&[u64]> {
+    let mut rest = &tree[1..];
+
+    (0..tree[0])
+        .map(|_| {
+            let (child, tail) = rest.split_at(subtree_len(rest).unwrap());
+            rest = tail;
+            child
+        })
+        .collect()
+}
+
+/// Length of the subtree at the beginning of the pre-order fan-outs, if
+/// there are enough of them.
+fn subtree_len(tree: &[u64]) -> Option<usize> {
+    let mut pending = 1u64;
+    let mut len = 0;
+
+    while pending > 0 {
+        pending = pending - 1 + tree.get(len)?;
+        len += 1;
+    }
+
+    Some(len)
+}
+
+/// Spawn the children from the tree, do the activity, then wait until the
+/// end of the lifetime and for the children. Errors of the activity are
+/// ignored, an attempt is enough to be noticed, but failed children are
+/// reported, the same as for a tree spawned by berserker.
+pub fn run(options: &StubOptions) -> Result<(), String> {
+    let start = Instant::now();
+    let id = process::id();
+
+    let children: Vec<_> = if options.tree.len() > 1 {
+        let (program, arg0) = executable();
+
+        children(&options.tree)
+            .into_iter()
+            .map(|tree| {
+                let options = StubOptions {
+                    tree: tree.to_vec(),
+                    ..options.clone()
+                };
+
+                Command::new(&program)
+                    .arg0(&arg0)
+                    .args(options.to_args())
+                    .spawn()
+                    .map_err(|e| format!("cannot spawn {program:?}: {e}"))
+            })
+            .collect::<Result<_, _>>()?
+    } else {
+        vec![]
+    };
+
+    let files: Vec<_> = (0..options.open_files)
+        .map(|i| env::temp_dir().join(format!("berserker-stub-{id}-{i}")))
+        .filter_map(|path| fs::File::create(&path).ok().map(|f| (path, f)))
+        .collect();
+
+    let _stream = options.connect.as_ref().and_then(|addr| {
+        let addr = addr.to_socket_addrs().ok()?.next()?;
+        TcpStream::connect_timeout(&addr, Duration::from_secs(1)).ok()
+    });
+
+    thread::sleep(
+        Duration::from_millis(options.lifetime_ms)
+            .saturating_sub(start.elapsed()),
+    );
+
+    for (path, _) in files {
+        let _ = fs::remove_file(path);
+    }
+
+    for mut child in children {
+        match child.wait() {
+            Ok(status) if status.success() => {}
+            status => return Err(format!("child failed: {status:?}")),
+        }
+    }
+
+    Ok(())
+}
+
+/// Where the stub was exec'd from and under which name, to exec children
+/// the same way. If the path is gone, e.g. the stub was exec'd from a file
+/// descriptor, the stub is exec'd via /proc/self/exe.
+fn executable() -> (PathBuf, OsString) {
+    let arg0 = env::args_os().next().unwrap_or_default();
+
+    let execfn =
+        unsafe { libc::getauxval(libc::AT_EXECFN) } as *const libc::c_char;
+    if !execfn.is_null() {
+        let path = unsafe { CStr::from_ptr(execfn) };
+        let path = PathBuf::from(OsStr::from_bytes(path.to_bytes()));
+        if path.exists() {
+            return (path, arg0);
+        }
+    }
+
+    (PathBuf::from("/proc/self/exe"), arg0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let options = StubOptions {
+            lifetime_ms: 100,
+            open_files: 2,
+            connect: Some(String::from("127.0.0.1:8080")),
+            tree: vec![2, 1, 0, 0],
+        };
+
+        let mut args = vec![String::from("/tmp/script")];
+        args.extend(options.to_args());
+        args.push(String::from("--lifetime-ms"));
+        args.push(String::from("invalid"));
+
+        assert_eq!(StubOptions::parse(&args), Ok(options));
+        assert!(StubOptions::parse(&[String::from("--open-files")]).is_err());
+
+        let tree = |tree: &str| {
+            StubOptions::parse(&[String::from("--tree"), String::from(tree)])
+        };
+        assert!(tree("2,0").is_err());
+        assert!(tree("1,0,0").is_err());
+        assert!(tree("0").is_ok());
+    }
+
+    #[test]
+    fn test_children() {
+        let tree = [3, 0, 2, 0, 0, 1, 0];
+        assert_eq!(children(&tree), vec![&[0][..], &[2, 0, 0], &[1, 0]]);
+        assert!(children(&[0]).is_empty());
+    }
+}
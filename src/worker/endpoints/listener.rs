@@ -0,0 +1,281 @@
+use std::{
+    fs, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::PathBuf,
+};
+
+use log::warn;
+use nix::sys::socket::{
+    self, SockFlag, SockType, SockaddrStorage, UnixAddr, setsockopt, sockopt,
+};
+
+use crate::{AddressFamily, Protocol};
+
+use super::poller::Poller;
+
+/// Where to listen, the port is appended at bind time.
+#[derive(Debug, Clone)]
+enum Address {
+    Ip {
+        addr: IpAddr,
+
+        /// Whether an IPv6 socket accepts only IPv6.
+        v6only: bool,
+    },
+
+    /// Socket file named after the port in the directory.
+    Path(PathBuf),
+
+    /// Abstract socket named after the port.
+    Abstract,
+}
+
+/// Kind of a listening socket to open on every port.
+#[derive(Debug, Clone)]
+pub(super) struct Endpoint {
+    address: Address,
+    kind: SockType,
+}
+
+/// Listening socket, which cleans up after itself.
+#[derive(Debug)]
+pub(super) struct Listener {
+    fd: OwnedFd,
+
+    /// Socket file to remove when closed.
+    path: Option<PathBuf>,
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+impl Endpoint {
+    fn bind(&self, port: u16) -> io::Result<Listener> {
+        let domain = match &self.address {
+            Address::Ip {
+                addr: IpAddr::V4(_),
+                ..
+            } => socket::AddressFamily::Inet,
+            Address::Ip {
+                addr: IpAddr::V6(_),
+                ..
+            } => socket::AddressFamily::Inet6,
+            Address::Path(_) | Address::Abstract => socket::AddressFamily::Unix,
+        };
+
+        let fd = socket::socket(
+            domain,
+            self.kind,
+            SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+            None,
+        )?;
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let mut path = None;
+
+        match &self.address {
+            Address::Ip { addr, v6only } => {
+                if addr.is_ipv6() {
+                    setsockopt(fd.as_raw_fd(), sockopt::Ipv6V6Only, v6only)?;
+                }
+
+                if self.kind == SockType::Stream {
+                    // The same as std TcpListener does
+                    setsockopt(fd.as_raw_fd(), sockopt::ReuseAddr, &true)?;
+                }
+
+                let addr = SocketAddr::new(*addr, port);
+                socket::bind(fd.as_raw_fd(), &SockaddrStorage::from(addr))?;
+            }
+            Address::Path(dir) => {
+                let file = dir.join(format!("{port}.{}", self.suffix()));
+                socket::bind(fd.as_raw_fd(), &UnixAddr::new(&file)?)?;
+                path = Some(file);
+            }
+            Address::Abstract => {
+                let name = format!("berserker-{port}.{}", self.suffix());
+                let addr = UnixAddr::new_abstract(name.as_bytes())?;
+                socket::bind(fd.as_raw_fd(), &addr)?;
+            }
+        }
+
+        if self.kind == SockType::Stream {
+            socket::listen(fd.as_raw_fd(), 128)?;
+        }
+
+        Ok(Listener { fd, path })
+    }
+
+    /// Stream and datagram unix sockets for the same port need different
+    /// names.
+    fn suffix(&self) -> &'static str {
+        match self.kind {
+            SockType::Stream => "stream",
+            _ => "dgram",
+        }
+    }
+}
+
+/// Every combination of addresses and protocols to listen on. For unix
+/// sockets TCP and UDP mean stream and datagram, and bind addresses are
+/// ignored.
+pub(super) fn endpoints(
+    protocol: Protocol,
+    family: AddressFamily,
+    bind_addrs: Vec<IpAddr>,
+    unix_dir: PathBuf,
+) -> Vec<Endpoint> {
+    let kinds = match protocol {
+        Protocol::Tcp => vec![SockType::Stream],
+        Protocol::Udp => vec![SockType::Datagram],
+        Protocol::Both => vec![SockType::Stream, SockType::Datagram],
+    };
+
+    let addresses = match family {
+        AddressFamily::Unix => {
+            if let Err(e) = fs::create_dir_all(&unix_dir) {
+                warn!("Failed to create {unix_dir:?}, {e}");
+            }
+
+            vec![Address::Path(unix_dir)]
+        }
+        AddressFamily::Abstract => vec![Address::Abstract],
+        AddressFamily::Ipv4 | AddressFamily::Ipv6 | AddressFamily::Dual => {
+            ip_addresses(family, bind_addrs)
+        }
+    };
+
+    addresses
+        .into_iter()
+        .flat_map(|address| {
+            kinds.iter().map(move |kind| Endpoint {
+                address: address.clone(),
+                kind: *kind,
+            })
+        })
+        .collect()
+}
+
+fn ip_addresses(
+    family: AddressFamily,
+    bind_addrs: Vec<IpAddr>,
+) -> Vec<Address> {
+    let bind_addrs = if bind_addrs.is_empty() {
+        match family {
+            AddressFamily::Ipv4 => vec![IpAddr::V4(Ipv4Addr::UNSPECIFIED)],
+            _ => vec![IpAddr::V6(Ipv6Addr::UNSPECIFIED)],
+        }
+    } else {
+        bind_addrs
+    };
+
+    bind_addrs
+        .into_iter()
+        .filter(|addr| {
+            let matches = match family {
+                AddressFamily::Ipv4 => addr.is_ipv4(),
+                AddressFamily::Ipv6 => addr.is_ipv6(),
+                _ => true,
+            };
+
+            if !matches {
+                warn!("Address {addr} doesn't match {family:?}, ignored");
+            }
+
+            matches
+        })
+        .map(|addr| Address::Ip {
+            addr,
+            v6only: family != AddressFamily::Dual,
+        })
+        .collect()
+}
+
+/// Listen on the port with every endpoint, watching for incoming
+/// connections if a poller is specified.
+pub(super) fn listen(
+    endpoints: &[Endpoint],
+    port: u16,
+    poller: Option<&Poller>,
+) -> io::Result<Vec<Listener>> {
+    endpoints
+        .iter()
+        .map(|endpoint| {
+            let listener = endpoint.bind(port)?;
+            if let Some(poller) = poller {
+                poller.register(&listener.fd, endpoint.kind)?;
+            }
+
+            Ok(listener)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        env,
+        os::{
+            linux::net::SocketAddrExt,
+            unix::net::{self, UnixDatagram, UnixStream},
+        },
+        process,
+    };
+
+    #[test]
+    fn test_unix() {
+        let dir =
+            env::temp_dir().join(format!("berserker-unix-{}", process::id()));
+        let endpoints =
+            endpoints(Protocol::Both, AddressFamily::Unix, vec![], dir.clone());
+        let listeners = listen(&endpoints, 8080, None).unwrap();
+        assert_eq!(listeners.len(), 2);
+
+        UnixStream::connect(dir.join("8080.stream")).unwrap();
+        UnixDatagram::unbound()
+            .unwrap()
+            .send_to(b"ping", dir.join("8080.dgram"))
+            .unwrap();
+
+        // Socket files go away together with the listeners
+        drop(listeners);
+        assert!(!dir.join("8080.stream").exists());
+        assert!(!dir.join("8080.dgram").exists());
+        fs::remove_dir(dir).unwrap();
+    }
+
+    #[test]
+    fn test_abstract() {
+        // Abstract names are shared by the whole network namespace
+        let port = process::id() as u16;
+        let endpoints = endpoints(
+            Protocol::Both,
+            AddressFamily::Abstract,
+            vec![],
+            "".into(),
+        );
+        let listeners = listen(&endpoints, port, None).unwrap();
+        assert_eq!(listeners.len(), 2);
+
+        let addr = |suffix| {
+            let name = format!("berserker-{port}.{suffix}");
+            net::SocketAddr::from_abstract_name(name).unwrap()
+        };
+        UnixStream::connect_addr(&addr("stream")).unwrap();
+        UnixDatagram::unbound()
+            .unwrap()
+            .send_to_addr(b"ping", &addr("dgram"))
+            .unwrap();
+
+        // Nothing is listening on the name anymore
+        drop(listeners);
+        assert!(UnixStream::connect_addr(&addr("stream")).is_err());
+    }
+}
@@ -0,0 +1,378 @@
+mod listener;
+mod poller;
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    env,
+    fmt::Display,
+    ops::RangeInclusive,
+    process,
+    time::{Duration, Instant},
+};
+
+use core_affinity::CoreId;
+use log::{debug, info, warn};
+use rand::{Rng, thread_rng};
+use rand_distr::Exp;
+
+use crate::{
+    BaseConfig, Distribution, Responder, Worker, WorkerError, Workload,
+    WorkloadConfig, worker::raise_nofile_limit,
+};
+
+use self::{
+    listener::{Endpoint, Listener, endpoints, listen},
+    poller::Poller,
+};
+
+/// How many random ports to try, if the picked one is already in use.
+const CHURN_RETRIES: usize = 16;
+
+struct EndpointWorkload {
+    restart_interval: u64,
+    lower: usize,
+    upper: usize,
+    distribution: Distribution,
+    endpoints: Vec<Endpoint>,
+    accept: bool,
+    responder: Responder,
+}
+
+pub struct EndpointWorker {
+    config: BaseConfig,
+    workload: EndpointWorkload,
+}
+
+impl EndpointWorker {
+    pub fn new(
+        workload: WorkloadConfig,
+        cpu: CoreId,
+        process: usize,
+        lower: usize,
+        upper: usize,
+    ) -> Self {
+        let WorkloadConfig {
+            restart_interval,
+            workload,
+            per_core: _,
+            workers: _,
+            duration: _,
+        } = workload;
+
+        let Workload::Endpoints {
+            distribution,
+            protocol,
+            family,
+            bind_addrs,
+            accept,
+            respond,
+            unix_dir,
+            ..
+        } = workload
+        else {
+            unreachable!()
+        };
+
+        EndpointWorker {
+            config: BaseConfig { cpu, process },
+            workload: EndpointWorkload {
+                restart_interval,
+                lower,
+                upper,
+                distribution,
+                endpoints: endpoints(
+                    protocol,
+                    family,
+                    bind_addrs,
+                    unix_dir.unwrap_or_else(|| {
+                        // Called before forking workers, so it's the same
+                        // for all of them
+                        env::temp_dir().join(format!(
+                            "berserker-endpoints-{}",
+                            process::id()
+                        ))
+                    }),
+                ),
+                accept: accept || respond != Responder::Close,
+                responder: respond,
+            },
+        }
+    }
+}
+
+impl Worker for EndpointWorker {
+    fn run_payload(&self) -> Result<(), WorkerError> {
+        info!("{self}");
+
+        let EndpointWorkload {
+            restart_interval,
+            lower,
+            upper,
+            distribution,
+            ref endpoints,
+            accept,
+            ref responder,
+        } = self.workload;
+
+        raise_nofile_limit();
+        let mut poller = Poller::new(responder.clone()).map_err(|e| {
+            WorkerError::InternalWithMessage(format!(
+                "cannot create epoll: {e}"
+            ))
+        })?;
+
+        if let Distribution::Churn {
+            arrival_rate,
+            departure_rate,
+            lower,
+            upper,
+        } = distribution
+        {
+            return self.churn(
+                arrival_rate,
+                departure_rate,
+                lower..=upper,
+                &mut poller,
+            );
+        }
+
+        // All the listeners are held by the current thread, so that their
+        // number is limited only by file descriptors
+        let sockets: Vec<_> = (lower..upper)
+            .filter_map(|port| {
+                listen(endpoints, port as u16, accept.then_some(&poller))
+                    .inspect_err(|e| warn!("Failed to listen on {port}, {e}"))
+                    .ok()
+            })
+            .flatten()
+            .collect();
+
+        info!(
+            "{}-{}: Listening on {} sockets",
+            self.config.cpu.id,
+            self.config.process,
+            sockets.len()
+        );
+
+        let deadline = Instant::now() + Duration::from_secs(restart_interval);
+        poller.run_until(deadline).map_err(|e| {
+            WorkerError::InternalWithMessage(format!("epoll failed: {e}"))
+        })?;
+
+        info!(
+            "{}-{}: Accepted {}, received {}, sent {} bytes",
+            self.config.cpu.id,
+            self.config.process,
+            poller.accepted,
+            poller.received,
+            poller.sent
+        );
+        Ok(())
+    }
+}
+
+impl EndpointWorker {
+    /// Open listeners on random ports from the range according to arrival
+    /// rate, and close each of them after a lifetime according to departure
+    /// rate. All listeners are held by the current thread.
+    fn churn(
+        &self,
+        arrival_rate: f64,
+        departure_rate: f64,
+        ports: RangeInclusive<u16>,
+        poller: &mut Poller,
+    ) -> Result<(), WorkerError> {
+        let BaseConfig { cpu, process } = self.config;
+        let endpoints = &self.workload.endpoints;
+        let accept = self.workload.accept;
+
+        let arrival = Exp::new(arrival_rate).unwrap();
+        let departure = Exp::new(departure_rate).unwrap();
+
+        let mut open: HashMap<u16, Vec<Listener>> = HashMap::new();
+        let mut expiry = BinaryHeap::new();
+        let mut next_arrival = Instant::now();
+        let mut stats = ChurnStats::default();
+        let mut start = Instant::now();
+
+        loop {
+            if start.elapsed().as_secs() > 10 {
+                info!(
+                    "{}-{}: Open {}, opened {}, closed {}, collisions {}, failures {}, accepted {}, received {}, sent {} bytes",
+                    cpu.id,
+                    process,
+                    open.len(),
+                    stats.opened,
+                    stats.closed,
+                    stats.collisions,
+                    stats.failures,
+                    poller.accepted,
+                    poller.received,
+                    poller.sent
+                );
+                start = Instant::now();
+            }
+
+            let now = Instant::now();
+
+            while let Some(Reverse((deadline, port))) = expiry.peek().copied() {
+                if deadline > now {
+                    break;
+                }
+
+                expiry.pop();
+                open.remove(&port);
+                stats.closed += 1;
+                debug!("{}-{}: Closed {}", cpu.id, process, port);
+            }
+
+            if next_arrival <= now {
+                match open_random(
+                    endpoints,
+                    ports.clone(),
+                    &open,
+                    accept.then_some(&*poller),
+                    &mut stats,
+                ) {
+                    Some((port, sockets)) => {
+                        let lifetime: f64 = thread_rng().sample(departure);
+                        let deadline = now + Duration::from_secs_f64(lifetime);
+
+                        open.insert(port, sockets);
+                        expiry.push(Reverse((deadline, port)));
+                        stats.opened += 1;
+                        debug!("{}-{}: Opened {}", cpu.id, process, port);
+                    }
+                    None => stats.failures += 1,
+                }
+
+                let interval: f64 = thread_rng().sample(arrival);
+                next_arrival = now + Duration::from_secs_f64(interval);
+            }
+
+            let wakeup = expiry
+                .peek()
+                .map_or(next_arrival, |Reverse((deadline, _))| {
+                    next_arrival.min(*deadline)
+                });
+            poller
+                .wait(wakeup.saturating_duration_since(Instant::now()))
+                .map_err(|e| {
+                    WorkerError::InternalWithMessage(format!(
+                        "epoll failed: {e}"
+                    ))
+                })?;
+        }
+    }
+}
+
+/// Counters reported periodically in the churn mode.
+#[derive(Debug, Default)]
+struct ChurnStats {
+    opened: u64,
+    closed: u64,
+
+    /// Picked ports, which were already in use by somebody else.
+    collisions: u64,
+
+    /// Arrivals for which no free port was found.
+    failures: u64,
+}
+
+/// Pick a random port not opened yet, and listen on it with every endpoint.
+/// If the port is taken by somebody else, try another one.
+fn open_random(
+    endpoints: &[Endpoint],
+    ports: RangeInclusive<u16>,
+    open: &HashMap<u16, Vec<Listener>>,
+    poller: Option<&Poller>,
+    stats: &mut ChurnStats,
+) -> Option<(u16, Vec<Listener>)> {
+    for _ in 0..CHURN_RETRIES {
+        let port = thread_rng().gen_range(ports.clone());
+        if open.contains_key(&port) {
+            stats.collisions += 1;
+            continue;
+        }
+
+        match listen(endpoints, port, poller) {
+            Ok(sockets) => return Some((port, sockets)),
+            Err(e) if e.raw_os_error() == Some(libc::EADDRINUSE) => {
+                stats.collisions += 1;
+            }
+            Err(e) => {
+                warn!("Failed to listen on {port}, {e}");
+                return None;
+            }
+        }
+    }
+
+    None
+}
+
+impl Display for EndpointWorker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AddressFamily, Protocol};
+
+    #[test]
+    fn test_open_random() {
+        // Abstract names are shared by the whole network namespace, keep
+        // away from other tests
+        let first = (process::id() % 30_000) as u16 + 30_000;
+        let endpoints = endpoints(
+            Protocol::Tcp,
+            AddressFamily::Abstract,
+            vec![],
+            "".into(),
+        );
+        let mut open = HashMap::new();
+        let mut stats = ChurnStats::default();
+
+        for _ in 0..2 {
+            let (port, sockets) = open_random(
+                &endpoints,
+                first..=first + 1,
+                &open,
+                None,
+                &mut stats,
+            )
+            .unwrap();
+            open.insert(port, sockets);
+        }
+        assert!(open.contains_key(&first) && open.contains_key(&(first + 1)));
+
+        // Every port of the range is open already
+        stats.collisions = 0;
+        let full =
+            open_random(&endpoints, first..=first + 1, &open, None, &mut stats);
+        assert!(full.is_none());
+        assert_eq!(stats.collisions, CHURN_RETRIES as u64);
+
+        // A closed port could be picked again
+        open.remove(&first);
+        let (port, _) =
+            open_random(&endpoints, first..=first, &open, None, &mut stats)
+                .unwrap();
+        assert_eq!(port, first);
+
+        // A port taken by somebody else is a collision as well
+        stats.collisions = 0;
+        let taken = open_random(
+            &endpoints,
+            first + 1..=first + 1,
+            &HashMap::new(),
+            None,
+            &mut stats,
+        );
+        assert!(taken.is_none());
+        assert_eq!(stats.collisions, CHURN_RETRIES as u64);
+    }
+}
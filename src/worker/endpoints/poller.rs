@@ -0,0 +1,455 @@
+use std::{
+    collections::HashMap,
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    time::{Duration, Instant},
+};
+
+use log::trace;
+use nix::{
+    errno::Errno,
+    sys::{
+        epoll::{
+            EpollCreateFlags, EpollEvent, EpollFlags, EpollOp, epoll_create1,
+            epoll_ctl, epoll_wait,
+        },
+        socket::{
+            MsgFlags, SockFlag, SockType, SockaddrStorage, accept4, recv,
+            recvfrom, send, sendto,
+        },
+    },
+};
+
+use crate::Responder;
+
+/// Marks listening TCP sockets in the event data, the rest is the fd.
+const LISTENER: u64 = 1 << 32;
+
+/// Marks accepted connections in the event data.
+const CONNECTION: u64 = 1 << 33;
+
+/// Requests larger than that are not going to be answered.
+const MAX_REQUEST: usize = 64 * 1024;
+
+/// Stop reading from a connection while it has more unsent data than that.
+const MAX_OUTPUT: usize = 1024 * 1024;
+
+/// Accepted connection being served.
+#[derive(Debug)]
+struct Connection {
+    fd: OwnedFd,
+
+    /// Received, but not yet processed data.
+    input: Vec<u8>,
+
+    /// Data to send as soon as the socket is writable.
+    output: Vec<u8>,
+
+    /// Close once the output is sent.
+    done: bool,
+
+    /// Whether a response for the request was already queued.
+    responded: bool,
+}
+
+/// Waits for incoming connections and datagrams on all registered sockets
+/// in a single thread, and serves them according to the responder.
+#[derive(Debug)]
+pub(super) struct Poller {
+    epoll: OwnedFd,
+    responder: Responder,
+    connections: HashMap<RawFd, Connection>,
+
+    /// Number of accepted connections.
+    pub accepted: u64,
+
+    /// Number of received datagrams.
+    pub received: u64,
+
+    /// Number of bytes sent in response.
+    pub sent: u64,
+}
+
+impl Poller {
+    pub fn new(responder: Responder) -> io::Result<Self> {
+        let epoll = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?;
+
+        Ok(Poller {
+            epoll: unsafe { OwnedFd::from_raw_fd(epoll) },
+            responder,
+            connections: HashMap::new(),
+            accepted: 0,
+            received: 0,
+            sent: 0,
+        })
+    }
+
+    /// Start watching a non-blocking socket. It's removed automatically
+    /// once closed.
+    pub fn register(&self, fd: &OwnedFd, kind: SockType) -> io::Result<()> {
+        let tag = match kind {
+            SockType::Stream => LISTENER,
+            _ => 0,
+        };
+
+        self.control(EpollOp::EpollCtlAdd, fd.as_raw_fd(), tag, true, false)
+    }
+
+    fn control(
+        &self,
+        op: EpollOp,
+        fd: RawFd,
+        tag: u64,
+        readable: bool,
+        writable: bool,
+    ) -> io::Result<()> {
+        let mut flags = EpollFlags::empty();
+        if readable {
+            flags |= EpollFlags::EPOLLIN;
+        }
+        if writable {
+            flags |= EpollFlags::EPOLLOUT;
+        }
+
+        let mut event = EpollEvent::new(flags, tag | fd as u64);
+        epoll_ctl(self.epoll.as_raw_fd(), op, fd, &mut event)?;
+        Ok(())
+    }
+
+    /// Handle events until the deadline.
+    pub fn run_until(&mut self, deadline: Instant) -> io::Result<()> {
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                return Ok(());
+            }
+
+            self.wait(timeout)?;
+        }
+    }
+
+    /// Wait for events not longer than the timeout, and handle them.
+    pub fn wait(&mut self, timeout: Duration) -> io::Result<()> {
+        let mut events = [EpollEvent::empty(); 64];
+        // Round up, otherwise a sub-millisecond timeout turns into a busy
+        // loop
+        let timeout = timeout.as_micros().div_ceil(1000) as isize;
+
+        let n = match epoll_wait(self.epoll.as_raw_fd(), &mut events, timeout) {
+            Ok(n) => n,
+            Err(Errno::EINTR) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        for event in &events[..n] {
+            let fd = (event.data() & !(LISTENER | CONNECTION)) as RawFd;
+
+            if event.data() & LISTENER != 0 {
+                self.accept(fd);
+            } else if event.data() & CONNECTION != 0 {
+                self.serve(fd, event.events());
+            } else {
+                self.receive(fd);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn accept(&mut self, fd: RawFd) {
+        loop {
+            let conn = match accept4(
+                fd,
+                SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+            ) {
+                Ok(conn) => unsafe { OwnedFd::from_raw_fd(conn) },
+                Err(Errno::EINTR) => continue,
+                Err(e) => {
+                    if e != Errno::EAGAIN {
+                        trace!("Failed to accept on {fd}, {e}");
+                    }
+                    return;
+                }
+            };
+
+            self.accepted += 1;
+
+            let mut connection = Connection {
+                fd: conn,
+                input: vec![],
+                output: vec![],
+                done: false,
+                responded: false,
+            };
+
+            match &self.responder {
+                Responder::Close => continue,
+                Responder::Banner { banner } => {
+                    connection.output.extend_from_slice(banner.as_bytes());
+                    connection.done = true;
+                }
+                Responder::Echo | Responder::Http { .. } => {}
+            }
+
+            let raw = connection.fd.as_raw_fd();
+            if let Err(e) =
+                self.control(EpollOp::EpollCtlAdd, raw, CONNECTION, true, false)
+            {
+                trace!("Failed to watch connection {raw}, {e}");
+                continue;
+            }
+
+            self.connections.insert(raw, connection);
+            self.flush(raw);
+        }
+    }
+
+    /// Read everything available from the connection, respond and send as
+    /// much as possible.
+    fn serve(&mut self, fd: RawFd, events: EpollFlags) {
+        let Some(connection) = self.connections.get_mut(&fd) else {
+            return;
+        };
+
+        // Errors and hangups are discovered while reading
+        if events.intersects(
+            EpollFlags::EPOLLIN | EpollFlags::EPOLLHUP | EpollFlags::EPOLLERR,
+        ) {
+            let mut buf = [0u8; 4096];
+
+            // Do not read more while the peer doesn't keep up with the
+            // output, the rest is read once it's flushed
+            while connection.input.len() + connection.output.len() < MAX_OUTPUT
+            {
+                match recv(fd, &mut buf, MsgFlags::empty()) {
+                    Ok(0) => {
+                        connection.done = true;
+                        break;
+                    }
+                    Ok(n) => connection.input.extend_from_slice(&buf[..n]),
+                    Err(Errno::EINTR) => continue,
+                    Err(Errno::EAGAIN) => break,
+                    Err(e) => {
+                        trace!("Failed to receive on {fd}, {e}");
+                        self.connections.remove(&fd);
+                        return;
+                    }
+                }
+            }
+
+            respond(&self.responder, connection);
+        }
+
+        self.flush(fd);
+    }
+
+    /// Send pending output of the connection, closing it if it's done. If
+    /// not everything could be sent, wait until it's writable again.
+    fn flush(&mut self, fd: RawFd) {
+        let Some(connection) = self.connections.get_mut(&fd) else {
+            return;
+        };
+
+        while !connection.output.is_empty() {
+            match send(fd, &connection.output, MsgFlags::MSG_NOSIGNAL) {
+                Ok(n) => {
+                    connection.output.drain(..n);
+                    self.sent += n as u64;
+                }
+                Err(Errno::EINTR) => continue,
+                Err(Errno::EAGAIN) => break,
+                Err(e) => {
+                    trace!("Failed to send on {fd}, {e}");
+                    self.connections.remove(&fd);
+                    return;
+                }
+            }
+        }
+
+        let pending = !connection.output.is_empty();
+        if !pending && connection.done {
+            self.connections.remove(&fd);
+            return;
+        }
+
+        let readable = connection.output.len() < MAX_OUTPUT;
+        if let Err(e) = self.control(
+            EpollOp::EpollCtlMod,
+            fd,
+            CONNECTION,
+            readable,
+            pending,
+        ) {
+            trace!("Failed to watch connection {fd}, {e}");
+            self.connections.remove(&fd);
+        }
+    }
+
+    fn receive(&mut self, fd: RawFd) {
+        let mut buf = [0u8; 2048];
+
+        loop {
+            let (n, addr) = match recvfrom::<SockaddrStorage>(fd, &mut buf) {
+                Ok(received) => received,
+                Err(Errno::EINTR) => continue,
+                Err(e) => {
+                    if e != Errno::EAGAIN {
+                        trace!("Failed to receive on {fd}, {e}");
+                    }
+                    return;
+                }
+            };
+
+            self.received += 1;
+
+            let reply = match &self.responder {
+                Responder::Echo => &buf[..n],
+                Responder::Banner { banner } => banner.as_bytes(),
+                Responder::Close | Responder::Http { .. } => continue,
+            };
+
+            if let Some(addr) = addr {
+                // Best effort, if there is no room the reply is dropped
+                if let Ok(n) = sendto(fd, reply, &addr, MsgFlags::MSG_DONTWAIT)
+                {
+                    self.sent += n as u64;
+                }
+            }
+        }
+    }
+}
+
+/// Process the input received so far, queueing the response.
+fn respond(responder: &Responder, connection: &mut Connection) {
+    match responder {
+        Responder::Echo => {
+            connection.output.append(&mut connection.input);
+        }
+        Responder::Http { status, body } => {
+            let complete = connection
+                .input
+                .windows(4)
+                .any(|window| window == b"\r\n\r\n");
+
+            if complete && !connection.responded {
+                connection
+                    .output
+                    .extend_from_slice(&http_response(*status, body));
+                connection.responded = true;
+                connection.done = true;
+            } else if connection.input.len() > MAX_REQUEST {
+                connection.done = true;
+            }
+
+            if connection.done {
+                connection.input.clear();
+            }
+        }
+        Responder::Close | Responder::Banner { .. } => {
+            connection.input.clear();
+        }
+    }
+}
+
+fn http_response(status: u16, body: &str) -> Vec<u8> {
+    let reason = match status {
+        200 => "OK",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    };
+
+    format!(
+        "HTTP/1.1 {status} {reason}\r\n\
+         Content-Type: text/plain\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    #[test]
+    fn test_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = OwnedFd::from(listener);
+
+        let mut poller = Poller::new(Responder::Http {
+            status: 404,
+            body: String::from("nothing"),
+        })
+        .unwrap();
+        poller.register(&listener, SockType::Stream).unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+            stream.write_all(b"Host: localhost\r\n\r\n").unwrap();
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        poller.run_until(deadline).unwrap();
+
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(response.ends_with("\r\n\r\nnothing"));
+        assert_eq!(poller.accepted, 1);
+    }
+
+    #[test]
+    fn test_echo_backpressure() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let listener = OwnedFd::from(listener);
+
+        let mut poller = Poller::new(Responder::Echo).unwrap();
+        poller.register(&listener, SockType::Stream).unwrap();
+
+        // Keep sending without reading anything back, until the socket
+        // buffers are full
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream
+                .set_write_timeout(Some(Duration::from_millis(500)))
+                .unwrap();
+
+            let chunk = [0u8; 64 * 1024];
+            let mut written = 0;
+            while written < 64 * MAX_OUTPUT && stream.write_all(&chunk).is_ok()
+            {
+                written += chunk.len();
+            }
+            (stream, written)
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        poller.run_until(deadline).unwrap();
+
+        let (_stream, written) = client.join().unwrap();
+        let connection = poller.connections.values().next().unwrap();
+        assert!(written > 2 * MAX_OUTPUT);
+        assert!(connection.output.len() < MAX_OUTPUT + 4096);
+    }
+}
@@ -1,15 +1,20 @@
+use std::{env, path::PathBuf};
+
 use core_affinity::CoreId;
+use log::warn;
+use nix::sys::resource::{Resource, getrlimit, setrlimit};
 use rand::{Rng, thread_rng};
 use rand_distr::{Uniform, Zipf};
 
 use crate::{
-    Distribution, Worker, Workload, WorkloadConfig,
+    Distribution, Worker, WorkerError, Workload, WorkloadConfig,
     worker::io_uring::IOUringWorker,
 };
 
 use self::{
     bpf::BpfWorker, endpoints::EndpointWorker, network::NetworkWorker,
     processes::ProcessesWorker, syscalls::SyscallsWorker,
+    threads::ThreadsWorker,
 };
 
 pub mod bpf;
@@ -18,6 +23,7 @@ pub mod io_uring;
 pub mod network;
 pub mod processes;
 pub mod syscalls;
+pub mod threads;
 
 pub fn new_worker(
     workload: WorkloadConfig,
@@ -30,7 +36,7 @@ pub fn new_worker(
         Workload::Processes { .. } => {
             Box::new(ProcessesWorker::new(workload, cpu, process))
         }
-        Workload::Endpoints { distribution } => {
+        Workload::Endpoints { distribution, .. } => {
             match distribution {
                 Distribution::Zipfian { n_ports, exponent } => {
                     let n_ports: f64 = thread_rng()
@@ -47,6 +53,9 @@ pub fn new_worker(
                     *lower_bound = *upper_bound;
                     *upper_bound += n_ports as usize;
                 }
+                // Ports are picked at runtime, collisions between workers
+                // are handled by the worker itself
+                Distribution::Churn { .. } => {}
             }
             Box::new(EndpointWorker::new(
                 workload,
@@ -56,6 +65,9 @@ pub fn new_worker(
                 *upper_bound,
             ))
         }
+        Workload::Threads { .. } => {
+            Box::new(ThreadsWorker::new(workload, cpu, process))
+        }
         Workload::Syscalls { .. } => {
             Box::new(SyscallsWorker::new(workload, cpu, process))
         }
@@ -70,3 +82,38 @@ pub fn new_worker(
         }
     }
 }
+
+/// Prepare the environment shared by all workers, if the workload needs one.
+/// Returns workers to start before the regular ones, e.g. the server side of
+/// the self-contained network workload.
+pub fn prepare(
+    workload: &WorkloadConfig,
+) -> Result<Vec<Box<dyn Worker>>, WorkerError> {
+    match workload.workload {
+        Workload::Network { .. } => network::prepare(workload),
+        _ => Ok(vec![]),
+    }
+}
+
+/// Find an executable the same way a shell would do.
+pub(crate) fn find_in_path(name: &str) -> Option<PathBuf> {
+    env::var_os("PATH").and_then(|paths| {
+        env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|path| path.is_file())
+    })
+}
+
+/// Every socket takes a file descriptor, raise the soft limit as high as
+/// allowed to be able to open tens of thousands of them.
+pub(crate) fn raise_nofile_limit() {
+    match getrlimit(Resource::RLIMIT_NOFILE) {
+        Ok((soft, hard)) if soft < hard => {
+            if let Err(e) = setrlimit(Resource::RLIMIT_NOFILE, hard, hard) {
+                warn!("Failed to raise open files limit, {e}");
+            }
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Failed to get open files limit, {e}"),
+    }
+}
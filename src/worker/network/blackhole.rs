@@ -0,0 +1,281 @@
+//! Device wrapper, which makes selected client flows deaf.
+
+use std::collections::HashSet;
+
+use smoltcp::{
+    phy::{self, Device, DeviceCapabilities, Medium},
+    time::Instant,
+    wire::{
+        EthernetFrame, EthernetProtocol, IpAddress, IpProtocol, IpVersion,
+        Ipv4Packet, Ipv6Packet, TcpPacket,
+    },
+};
+
+/// Drops received TCP packets destined to deaf local endpoints, and
+/// transmitted TCP packets sent from mute ones, as if the packets were lost
+/// on the way. A flow which has disappeared doesn't answer the peer with
+/// RST, and a mute flow never gets its SYN to the peer, so the handshake
+/// times out without the peer ever seeing the connection.
+pub(super) struct Blackhole<D: Device> {
+    inner: D,
+    medium: Medium,
+    endpoints: HashSet<(IpAddress, u16)>,
+    muted: HashSet<(IpAddress, u16)>,
+}
+
+impl<D: Device> Blackhole<D> {
+    pub fn new(inner: D) -> Self {
+        Blackhole {
+            medium: inner.capabilities().medium,
+            inner,
+            endpoints: HashSet::new(),
+            muted: HashSet::new(),
+        }
+    }
+
+    /// Stop receiving packets for the endpoint.
+    pub fn insert(&mut self, endpoint: (IpAddress, u16)) {
+        self.endpoints.insert(endpoint);
+    }
+
+    /// Stop transmitting packets from the endpoint.
+    pub fn mute(&mut self, endpoint: (IpAddress, u16)) {
+        self.muted.insert(endpoint);
+    }
+
+    /// Let the endpoint both receive and transmit again.
+    pub fn remove(&mut self, endpoint: (IpAddress, u16)) {
+        self.endpoints.remove(&endpoint);
+        self.muted.remove(&endpoint);
+    }
+}
+
+impl<D: Device> Device for Blackhole<D> {
+    type RxToken<'a>
+        = RxToken<'a, D::RxToken<'a>>
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a, D::TxToken<'a>>
+    where
+        Self: 'a;
+
+    fn receive(
+        &mut self,
+        timestamp: Instant,
+    ) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        let (rx, tx) = self.inner.receive(timestamp)?;
+        let rx = RxToken {
+            inner: rx,
+            medium: self.medium,
+            endpoints: &self.endpoints,
+        };
+        let tx = TxToken {
+            inner: tx,
+            medium: self.medium,
+            muted: &self.muted,
+        };
+
+        Some((rx, tx))
+    }
+
+    fn transmit(&mut self, timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        Some(TxToken {
+            inner: self.inner.transmit(timestamp)?,
+            medium: self.medium,
+            muted: &self.muted,
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+}
+
+pub(super) struct RxToken<'a, Rx: phy::RxToken> {
+    inner: Rx,
+    medium: Medium,
+    endpoints: &'a HashSet<(IpAddress, u16)>,
+}
+
+impl<Rx: phy::RxToken> phy::RxToken for RxToken<'_, Rx> {
+    fn consume<R, F>(self, f: F) -> R
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let (medium, endpoints) = (self.medium, self.endpoints);
+
+        self.inner.consume(|buffer| {
+            let blackholed = !endpoints.is_empty()
+                && addresses(buffer, medium)
+                    .is_some_and(|(_, dst)| endpoints.contains(&dst));
+
+            if blackholed {
+                // An empty packet is discarded as malformed
+                f(&[])
+            } else {
+                f(buffer)
+            }
+        })
+    }
+}
+
+pub(super) struct TxToken<'a, Tx: phy::TxToken> {
+    inner: Tx,
+    medium: Medium,
+    muted: &'a HashSet<(IpAddress, u16)>,
+}
+
+impl<Tx: phy::TxToken> phy::TxToken for TxToken<'_, Tx> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        if self.muted.is_empty() {
+            return self.inner.consume(len, f);
+        }
+
+        // The packet has to be built before it's known where it comes from,
+        // and whatever is built in the inner buffer is sent out.
+        let mut packet = vec![0; len];
+        let result = f(&mut packet);
+
+        let muted = addresses(&packet, self.medium)
+            .is_some_and(|(src, _)| self.muted.contains(&src));
+        if !muted {
+            self.inner
+                .consume(len, |buffer| buffer.copy_from_slice(&packet));
+        }
+
+        result
+    }
+
+    fn set_meta(&mut self, meta: phy::PacketMeta) {
+        self.inner.set_meta(meta);
+    }
+}
+
+/// Source and destination addresses and ports of a TCP packet, possibly
+/// inside of an Ethernet frame.
+fn addresses(
+    packet: &[u8],
+    medium: Medium,
+) -> Option<((IpAddress, u16), (IpAddress, u16))> {
+    let packet = match medium {
+        Medium::Ethernet => {
+            let frame = EthernetFrame::new_checked(packet).ok()?;
+            match frame.ethertype() {
+                EthernetProtocol::Ipv4 | EthernetProtocol::Ipv6 => {}
+                _ => return None,
+            }
+
+            &packet[EthernetFrame::<&[u8]>::header_len()..]
+        }
+        _ => packet,
+    };
+
+    match IpVersion::of_packet(packet).ok()? {
+        IpVersion::Ipv4 => {
+            let packet = Ipv4Packet::new_checked(packet).ok()?;
+            if packet.next_header() != IpProtocol::Tcp {
+                return None;
+            }
+
+            let tcp = TcpPacket::new_checked(packet.payload()).ok()?;
+            Some((
+                (IpAddress::Ipv4(packet.src_addr()), tcp.src_port()),
+                (IpAddress::Ipv4(packet.dst_addr()), tcp.dst_port()),
+            ))
+        }
+        IpVersion::Ipv6 => {
+            let packet = Ipv6Packet::new_checked(packet).ok()?;
+            if packet.next_header() != IpProtocol::Tcp {
+                return None;
+            }
+
+            let tcp = TcpPacket::new_checked(packet.payload()).ok()?;
+            Some((
+                (IpAddress::Ipv6(packet.src_addr()), tcp.src_port()),
+                (IpAddress::Ipv6(packet.dst_addr()), tcp.dst_port()),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smoltcp::{
+        iface::{Config, Interface, SocketSet},
+        phy::Loopback,
+        socket::tcp,
+        wire::{HardwareAddress, IpCidr, Ipv4Address},
+    };
+
+    fn socket() -> tcp::Socket<'static> {
+        tcp::Socket::new(
+            tcp::SocketBuffer::new(vec![0; 1024]),
+            tcp::SocketBuffer::new(vec![0; 1024]),
+        )
+    }
+
+    #[test]
+    fn test_mute() {
+        let addr = IpAddress::Ipv4(Ipv4Address::new(127, 0, 0, 1));
+        let mut device = Blackhole::new(Loopback::new(Medium::Ip));
+        let mut iface = Interface::new(
+            Config::new(HardwareAddress::Ip),
+            &mut device,
+            Instant::ZERO,
+        );
+        iface.update_ip_addrs(|addrs| {
+            addrs.push(IpCidr::new(addr, 8)).unwrap();
+        });
+
+        let mut sockets = SocketSet::new(vec![]);
+        let mut server = socket();
+        server.listen(8080).unwrap();
+        let server = sockets.add(server);
+
+        let mut muted = socket();
+        muted
+            .connect(iface.context(), (addr, 8080), (addr, 49152))
+            .unwrap();
+        let muted = sockets.add(muted);
+        device.mute((addr, 49152));
+
+        // Retransmissions of SYN get lost as well
+        for ms in (0..10_000).step_by(100) {
+            iface.poll(Instant::from_millis(ms), &mut device, &mut sockets);
+        }
+        assert_eq!(
+            sockets.get::<tcp::Socket>(muted).state(),
+            tcp::State::SynSent
+        );
+        assert_eq!(
+            sockets.get::<tcp::Socket>(server).state(),
+            tcp::State::Listen
+        );
+
+        // Once the endpoint is reused, the connection goes through
+        sockets.remove(muted);
+        device.remove((addr, 49152));
+        let mut client = socket();
+        client
+            .connect(iface.context(), (addr, 8080), (addr, 49152))
+            .unwrap();
+        let client = sockets.add(client);
+
+        for ms in (10_000..11_000).step_by(100) {
+            iface.poll(Instant::from_millis(ms), &mut device, &mut sockets);
+        }
+        assert_eq!(
+            sockets.get::<tcp::Socket>(client).state(),
+            tcp::State::Established
+        );
+        assert_eq!(
+            sockets.get::<tcp::Socket>(server).state(),
+            tcp::State::Established
+        );
+    }
+}
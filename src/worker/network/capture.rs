@@ -0,0 +1,230 @@
+//! Packet capture of the client traffic into pcap files.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+use smoltcp::{
+    phy::{PcapLinkType, PcapSink},
+    time::Instant,
+};
+
+use crate::{Capture, WorkerError};
+
+/// Size of the pcap global header.
+const GLOBAL_HEADER_LEN: u64 = 24;
+
+/// Pcap file with optional rotation, doing nothing if the capture is not
+/// configured. Packets are truncated to the snaplen, and the file is
+/// flushed after every packet so that nothing is lost when the worker is
+/// killed.
+pub(super) struct PcapFile {
+    path: PathBuf,
+    snaplen: u32,
+    rotate_size: Option<u64>,
+    rotate_count: usize,
+
+    file: Option<BufWriter<File>>,
+    written: u64,
+    link_type: PcapLinkType,
+}
+
+impl PcapFile {
+    pub fn new(
+        capture: Option<&Capture>,
+        index: usize,
+        workers: usize,
+    ) -> Result<Self, WorkerError> {
+        let Some(capture) = capture else {
+            return Ok(PcapFile {
+                path: PathBuf::new(),
+                snaplen: 0,
+                rotate_size: None,
+                rotate_count: 0,
+                file: None,
+                written: 0,
+                link_type: PcapLinkType::Ip,
+            });
+        };
+
+        let path = if workers > 1 {
+            worker_path(&capture.path, index)
+        } else {
+            capture.path.clone()
+        };
+
+        let file = File::create(&path).map_err(|e| {
+            WorkerError::InternalWithMessage(format!(
+                "failed to create {}, {e}",
+                path.display()
+            ))
+        })?;
+
+        Ok(PcapFile {
+            path,
+            snaplen: capture.snaplen,
+            rotate_size: capture.rotate_size,
+            rotate_count: capture.rotate_count,
+            file: Some(BufWriter::new(file)),
+            written: 0,
+            link_type: PcapLinkType::Ip,
+        })
+    }
+
+    /// Move the current file to path.1, shifting older ones, and start a new
+    /// one.
+    fn rotate(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            Write::flush(&mut file)?;
+        }
+
+        if self.rotate_count == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            for i in (1..self.rotate_count).rev() {
+                let older = rotated_path(&self.path, i);
+                if older.exists() {
+                    fs::rename(&older, rotated_path(&self.path, i + 1))?;
+                }
+            }
+
+            fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        }
+
+        self.file = Some(BufWriter::new(File::create(&self.path)?));
+        self.written = 0;
+        self.global_header(self.link_type);
+        Ok(())
+    }
+
+    fn stop(&mut self, e: io::Error) {
+        warn!("Stopped capturing into {}, {e}", self.path.display());
+        self.file = None;
+    }
+}
+
+impl PcapSink for PcapFile {
+    fn write(&mut self, data: &[u8]) {
+        let Some(file) = &mut self.file else {
+            return;
+        };
+
+        match file.write_all(data) {
+            Ok(()) => self.written += data.len() as u64,
+            Err(e) => self.stop(e),
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(file) = &mut self.file
+            && let Err(e) = Write::flush(file)
+        {
+            self.stop(e);
+        }
+    }
+
+    fn global_header(&mut self, link_type: PcapLinkType) {
+        self.link_type = link_type;
+
+        self.write_u32(0xa1b2c3d4); // magic number
+        self.write_u16(2); // major version
+        self.write_u16(4); // minor version
+        self.write_u32(0); // timezone (= UTC)
+        self.write_u32(0); // accuracy (not used)
+        self.write_u32(self.snaplen); // maximum packet length
+        self.write_u32(link_type.into()); // link-layer header type
+    }
+
+    fn packet(&mut self, timestamp: Instant, packet: &[u8]) {
+        if self.file.is_none() {
+            return;
+        }
+
+        if self.rotate_size.is_some_and(|size| {
+            self.written > GLOBAL_HEADER_LEN && self.written >= size
+        }) && let Err(e) = self.rotate()
+        {
+            self.stop(e);
+            return;
+        }
+
+        let captured = packet.len().min(self.snaplen as usize);
+
+        self.write_u32(timestamp.secs() as u32);
+        self.write_u32(timestamp.micros() as u32);
+        self.write_u32(captured as u32);
+        self.write_u32(packet.len() as u32);
+        self.write(&packet[..captured]);
+        self.flush();
+    }
+}
+
+/// Path with the worker index added to the file name, before the extension.
+fn worker_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}-{index}.{}", ext.to_string_lossy()),
+        None => format!("{stem}-{index}"),
+    };
+
+    path.with_file_name(name)
+}
+
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{n}"));
+    PathBuf::from(rotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_worker_path() {
+        assert_eq!(
+            worker_path(Path::new("/tmp/capture.pcap"), 2),
+            PathBuf::from("/tmp/capture-2.pcap")
+        );
+        assert_eq!(
+            worker_path(Path::new("capture"), 0),
+            PathBuf::from("capture-0")
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = env::temp_dir()
+            .join(format!("berserker-capture-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let capture = Capture {
+            path: dir.join("test.pcap"),
+            snaplen: 64,
+            rotate_size: Some(200),
+            rotate_count: 2,
+        };
+        let mut pcap = PcapFile::new(Some(&capture), 0, 1).unwrap();
+        pcap.global_header(PcapLinkType::Ip);
+
+        // Every packet takes 16 bytes of header plus 64 bytes of data, so
+        // that every file holds three of them
+        for _ in 0..10 {
+            pcap.packet(Instant::from_secs(1), &[0u8; 1500]);
+        }
+        drop(pcap);
+
+        let size = |path: PathBuf| fs::metadata(path).unwrap().len();
+        assert_eq!(size(capture.path.clone()), 24 + 80);
+        assert_eq!(size(rotated_path(&capture.path, 1)), 24 + 3 * 80);
+        assert_eq!(size(rotated_path(&capture.path, 2)), 24 + 3 * 80);
+        assert!(!rotated_path(&capture.path, 3).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+//! Where flows of the network client connect to.
+
+use std::{
+    collections::HashSet,
+    fs,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, warn};
+use rand::{Rng, distributions::WeightedIndex, thread_rng};
+use rand_distr::{Distribution, Zipf};
+use smoltcp::wire::IpAddress;
+
+use crate::{Destination, Popularity, WorkerError};
+
+/// How often to look for new listening sockets in the inbound mode.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// TCP_LISTEN in /proc/net/tcp.
+const TCP_LISTEN: &str = "0A";
+
+/// TCP_CLOSE in /proc/net/udp, meaning an unconnected socket.
+const UDP_UNCONNECTED: &str = "07";
+
+/// A set of destinations to pick from according to their popularity.
+#[derive(Debug)]
+struct Choice {
+    endpoints: Vec<(IpAddress, u16)>,
+    weights: Option<WeightedIndex<f64>>,
+    zipf: Option<Zipf<f64>>,
+}
+
+impl Choice {
+    fn new(
+        endpoints: Vec<(IpAddress, u16)>,
+        weights: Vec<f64>,
+        popularity: Popularity,
+    ) -> Result<Self, WorkerError> {
+        let mut choice = Choice {
+            endpoints,
+            weights: None,
+            zipf: None,
+        };
+
+        if choice.endpoints.is_empty() {
+            return Ok(choice);
+        }
+
+        match popularity {
+            Popularity::Weighted => {
+                let weights = WeightedIndex::new(weights).map_err(|e| {
+                    WorkerError::InternalWithMessage(format!(
+                        "invalid destination weights, {e}"
+                    ))
+                })?;
+                choice.weights = Some(weights);
+            }
+            Popularity::Zipf { exponent } => {
+                let n = choice.endpoints.len() as u64;
+                let zipf = Zipf::new(n, exponent).map_err(|e| {
+                    WorkerError::InternalWithMessage(format!(
+                        "invalid destination popularity, {e}"
+                    ))
+                })?;
+                choice.zipf = Some(zipf);
+            }
+        }
+
+        Ok(choice)
+    }
+
+    fn pick(&self) -> Option<(IpAddress, u16)> {
+        let index = if let Some(weights) = &self.weights {
+            weights.sample(&mut thread_rng())
+        } else {
+            let rank: f64 = thread_rng().sample(self.zipf.as_ref()?);
+            rank as usize - 1
+        };
+
+        self.endpoints.get(index).copied()
+    }
+}
+
+/// Destinations for TCP and UDP flows, either configured or found on the
+/// host in the inbound mode.
+#[derive(Debug)]
+pub(super) struct Destinations {
+    tcp: Choice,
+    udp: Choice,
+    popularity: Popularity,
+
+    /// Address to reach wildcard listeners, if in the inbound mode.
+    inbound: Option<IpAddress>,
+    refreshed: SystemTime,
+}
+
+impl Destinations {
+    /// Destinations with the specified popularity. If none are configured,
+    /// the default one is used. In the inbound mode listening sockets are
+    /// looked up on the host, wildcard ones are reached via the default
+    /// address.
+    pub fn new(
+        configured: &[Destination],
+        popularity: Popularity,
+        inbound: bool,
+        default: (IpAddress, u16),
+    ) -> Result<Self, WorkerError> {
+        let (endpoints, weights): (Vec<_>, Vec<_>) = if configured.is_empty() {
+            (vec![default], vec![1.0])
+        } else {
+            configured
+                .iter()
+                .filter(|dest| {
+                    let matches = same_family(dest.address, default.0);
+                    if !matches {
+                        warn!(
+                            "Destination {} doesn't match {}, ignored",
+                            dest.address, default.0
+                        );
+                    }
+                    matches
+                })
+                .map(|dest| ((dest.address.into(), dest.port), dest.weight))
+                .unzip()
+        };
+
+        let mut destinations = Destinations {
+            tcp: Choice::new(endpoints.clone(), weights.clone(), popularity)?,
+            udp: Choice::new(endpoints, weights, popularity)?,
+            popularity,
+            inbound: inbound.then_some(default.0),
+            refreshed: SystemTime::now(),
+        };
+
+        if inbound {
+            destinations.refresh()?;
+        }
+
+        Ok(destinations)
+    }
+
+    /// Pick a destination for a new flow, if there are any.
+    pub fn pick(&mut self, datagram: bool) -> Option<(IpAddress, u16)> {
+        if self.inbound.is_some()
+            && self.refreshed.elapsed().unwrap_or_default() > REFRESH_INTERVAL
+            && let Err(e) = self.refresh()
+        {
+            warn!("Failed to look up listening sockets, {e}");
+        }
+
+        if datagram {
+            self.udp.pick()
+        } else {
+            self.tcp.pick()
+        }
+    }
+
+    /// Look up listening sockets on the host again.
+    fn refresh(&mut self) -> Result<(), WorkerError> {
+        let Some(address) = self.inbound else {
+            return Ok(());
+        };
+
+        let tcp = listeners(&["/proc/net/tcp", "/proc/net/tcp6"], TCP_LISTEN);
+        let udp =
+            listeners(&["/proc/net/udp", "/proc/net/udp6"], UDP_UNCONNECTED);
+
+        let reachable = |listeners: Vec<(IpAddr, u16)>| -> Vec<_> {
+            listeners
+                .into_iter()
+                .filter_map(|(addr, port)| {
+                    reachable_via(addr, address).map(|addr| (addr, port))
+                })
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect()
+        };
+
+        let tcp = reachable(tcp);
+        let udp = reachable(udp);
+        debug!("Found {} TCP and {} UDP listeners", tcp.len(), udp.len());
+
+        let (tcp_weights, udp_weights) =
+            (vec![1.0; tcp.len()], vec![1.0; udp.len()]);
+        self.tcp = Choice::new(tcp, tcp_weights, self.popularity)?;
+        self.udp = Choice::new(udp, udp_weights, self.popularity)?;
+        self.refreshed = SystemTime::now();
+        Ok(())
+    }
+}
+
+fn same_family(addr: IpAddr, other: IpAddress) -> bool {
+    matches!(
+        (addr, other),
+        (IpAddr::V4(_), IpAddress::Ipv4(_))
+            | (IpAddr::V6(_), IpAddress::Ipv6(_))
+    )
+}
+
+/// Address to connect to a listener bound to the address via the tun
+/// device, if it's reachable at all. Wildcard listeners are reached via the
+/// default address, IPv6 wildcard ones are assumed to accept IPv4 as well.
+/// Loopback listeners are not reachable from outside.
+fn reachable_via(addr: IpAddr, default: IpAddress) -> Option<IpAddress> {
+    if addr.is_loopback() {
+        return None;
+    }
+
+    if addr.is_unspecified() {
+        return match (addr, default) {
+            (IpAddr::V4(_), IpAddress::Ipv6(_)) => None,
+            _ => Some(default),
+        };
+    }
+
+    same_family(addr, default).then(|| addr.into())
+}
+
+/// Local addresses of sockets in the state from /proc/net files.
+fn listeners(files: &[&str], state: &str) -> Vec<(IpAddr, u16)> {
+    files
+        .iter()
+        .filter_map(|file| fs::read_to_string(file).ok())
+        .flat_map(|content| {
+            content
+                .lines()
+                .skip(1)
+                .filter_map(|line| parse_socket(line, state))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Parse a line from /proc/net/{tcp,udp}[6], returning the local address if
+/// the socket is in the specified state.
+fn parse_socket(line: &str, state: &str) -> Option<(IpAddr, u16)> {
+    let mut fields = line.split_whitespace();
+    let local = fields.nth(1)?;
+    let st = fields.nth(1)?;
+
+    if st != state {
+        return None;
+    }
+
+    let (addr, port) = local.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+
+    // The address is printed as 32 bit words in the host byte order
+    let words = (0..addr.len() / 8)
+        .map(|i| u32::from_str_radix(&addr[i * 8..(i + 1) * 8], 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+    let bytes: Vec<u8> =
+        words.iter().flat_map(|word| word.to_ne_bytes()).collect();
+
+    let addr = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => return None,
+    };
+
+    Some((addr, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_socket() {
+        let tcp = "   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 12345 1 0000000000000000 100 0 0 10 0";
+        assert_eq!(
+            parse_socket(tcp, TCP_LISTEN),
+            Some(("127.0.0.1".parse().unwrap(), 8080))
+        );
+        assert_eq!(parse_socket(tcp, UDP_UNCONNECTED), None);
+
+        let tcp6 = "   1: 0000000000000000FFFF00000100000A:0050 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 12346 1 0000000000000000 100 0 0 10 0";
+        assert_eq!(
+            parse_socket(tcp6, TCP_LISTEN),
+            Some(("::ffff:10.0.0.1".parse().unwrap(), 80))
+        );
+
+        let header = "  sl  local_address rem_address   st tx_queue rx_queue";
+        assert_eq!(parse_socket(header, TCP_LISTEN), None);
+    }
+
+    #[test]
+    fn test_reachable_via() {
+        let default = IpAddress::v4(10, 0, 0, 1);
+
+        assert_eq!(
+            reachable_via("0.0.0.0".parse().unwrap(), default),
+            Some(default)
+        );
+        assert_eq!(
+            reachable_via("::".parse().unwrap(), default),
+            Some(default)
+        );
+        assert_eq!(reachable_via("127.0.0.1".parse().unwrap(), default), None);
+        assert_eq!(
+            reachable_via("10.0.0.5".parse().unwrap(), default),
+            Some(IpAddress::v4(10, 0, 0, 5))
+        );
+        assert_eq!(reachable_via("fd00::1".parse().unwrap(), default), None);
+    }
+}
@@ -0,0 +1,155 @@
+//! Tun or tap device of the network client.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io, mem,
+    os::{
+        fd::{AsRawFd, IntoRawFd},
+        unix::fs::OpenOptionsExt,
+    },
+};
+
+use smoltcp::{
+    phy::{Medium, TunTapInterface},
+    wire::{EthernetAddress, EthernetFrame},
+};
+
+use crate::{DeviceMedium, Sharing, TunDevice, WorkerError};
+
+/// Name of the device the worker with the index uses.
+pub(super) fn device_name(
+    device: &TunDevice,
+    index: usize,
+) -> Result<String, WorkerError> {
+    let name = match device.sharing {
+        Sharing::Shared => device.name.clone(),
+        Sharing::PerWorker => format!("{}-{}", device.name, index),
+    };
+
+    if name.is_empty() || name.len() >= libc::IFNAMSIZ {
+        return Err(WorkerError::InternalWithMessage(format!(
+            "invalid device name {name}"
+        )));
+    }
+
+    Ok(name)
+}
+
+/// Attach to the device, creating it if needed. If the device is shared with
+/// other workers, every one of them gets its own queue, and the kernel
+/// steers packets of a flow to the queue the flow was sent from.
+pub(super) fn open(
+    name: &str,
+    medium: DeviceMedium,
+    multi_queue: bool,
+) -> Result<TunTapInterface, WorkerError> {
+    let file = attach(name, medium, multi_queue)?;
+
+    // IP MTU of the device, smoltcp counts the Ethernet header as well
+    let mtu = fs::read_to_string(format!("/sys/class/net/{name}/mtu"))
+        .ok()
+        .and_then(|mtu| mtu.trim().parse().ok())
+        .unwrap_or(1500);
+
+    let (medium, mtu) = match medium {
+        DeviceMedium::Tun => (Medium::Ip, mtu),
+        DeviceMedium::Tap => {
+            (Medium::Ethernet, mtu + EthernetFrame::<&[u8]>::header_len())
+        }
+    };
+
+    TunTapInterface::from_fd(file.into_raw_fd(), medium, mtu)
+        .map_err(|e| error(name, e))
+}
+
+/// Create a device, which stays after berserker detaches from it.
+pub(super) fn create(
+    name: &str,
+    medium: DeviceMedium,
+    multi_queue: bool,
+) -> Result<(), WorkerError> {
+    let file = attach(name, medium, multi_queue)?;
+
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), libc::TUNSETPERSIST, 1) };
+    if ret == -1 {
+        return Err(error(name, io::Error::last_os_error()));
+    }
+
+    Ok(())
+}
+
+fn error(name: &str, e: io::Error) -> WorkerError {
+    WorkerError::InternalWithMessage(format!(
+        "failed to open device {name}, {e}"
+    ))
+}
+
+fn attach(
+    name: &str,
+    medium: DeviceMedium,
+    multi_queue: bool,
+) -> Result<File, WorkerError> {
+    let error = |e: io::Error| error(name, e);
+
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open("/dev/net/tun")
+        .map_err(error)?;
+
+    let mut flags = libc::IFF_NO_PI
+        | match medium {
+            DeviceMedium::Tun => libc::IFF_TUN,
+            DeviceMedium::Tap => libc::IFF_TAP,
+        };
+
+    if multi_queue {
+        flags |= libc::IFF_MULTI_QUEUE;
+    }
+
+    let mut ifreq: libc::ifreq = unsafe { mem::zeroed() };
+    for (dst, src) in ifreq.ifr_name.iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    ifreq.ifr_ifru.ifru_flags = flags as libc::c_short;
+
+    let ret =
+        unsafe { libc::ioctl(file.as_raw_fd(), libc::TUNSETIFF, &mut ifreq) };
+    if ret == -1 {
+        return Err(error(io::Error::last_os_error()));
+    }
+
+    Ok(file)
+}
+
+/// Random locally administered unicast MAC address.
+pub(super) fn generate_mac() -> EthernetAddress {
+    let mut bytes: [u8; 6] = rand::random();
+    bytes[0] = (bytes[0] & 0xfe) | 0x02;
+    EthernetAddress(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_name() {
+        let mut device = TunDevice::default();
+        assert_eq!(device_name(&device, 3).unwrap(), "berserker0");
+
+        device.sharing = Sharing::PerWorker;
+        assert_eq!(device_name(&device, 3).unwrap(), "berserker0-3");
+
+        device.name = String::from("averyverylongname");
+        assert!(device_name(&device, 3).is_err());
+    }
+
+    #[test]
+    fn test_generate_mac() {
+        let mac = generate_mac();
+        assert!(mac.is_unicast());
+        assert!(mac.is_local());
+    }
+}
@@ -0,0 +1,505 @@
+//! ICMP and raw IP traffic of the network client. Packets are crafted with
+//! spoofed client addresses and sent via raw sockets, since icmp sockets
+//! always use the interface address as the source. Echo replies come back
+//! to icmp sockets bound to the echo identifier of every flow.
+
+use std::fmt::Display;
+
+use log::trace;
+use rand::{Rng, thread_rng};
+use rand_distr::Exp;
+use smoltcp::{
+    iface::{SocketHandle, SocketSet},
+    phy::ChecksumCapabilities,
+    socket::{icmp, raw},
+    time::{Duration, Instant},
+    wire::{
+        Icmpv4DstUnreachable, Icmpv4Packet, Icmpv4Repr, Icmpv6DstUnreachable,
+        Icmpv6Packet, Icmpv6Repr, IpAddress, IpProtocol, IpRepr, IpVersion,
+        Ipv4Repr, Ipv6Repr, UdpPacket,
+    },
+};
+
+use crate::{Icmp, WorkerError};
+
+use super::destinations::Destinations;
+
+const HOP_LIMIT: u8 = 64;
+
+/// Size of the UDP header quoted in unreachable messages.
+const UDP_HEADER_LEN: usize = 8;
+
+/// Counters of ICMP and raw traffic, reported together with the client ones.
+#[derive(Debug, Default)]
+pub(super) struct IcmpStats {
+    echo_requests: u64,
+    echo_replies: u64,
+    unreachable: u64,
+    raw: u64,
+}
+
+impl Display for IcmpStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "echo requests {}, echo replies {}, unreachable {}, raw {}",
+            self.echo_requests, self.echo_replies, self.unreachable, self.raw
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Kind {
+    Echo,
+    Unreachable,
+    Raw,
+}
+
+/// Client address sending echo requests, with a socket to receive replies.
+#[derive(Debug)]
+struct Flow {
+    local: (IpAddress, u16),
+    ident: u16,
+    seq_no: u16,
+    handle: SocketHandle,
+}
+
+pub(super) struct IcmpTraffic {
+    config: Icmp,
+
+    /// Raw sockets to send ICMP and raw protocol packets.
+    icmp: SocketHandle,
+    raw: SocketHandle,
+
+    flows: Vec<Flow>,
+    next_flow: usize,
+
+    /// Every kind of traffic with the time the next packet is due, if
+    /// enabled.
+    schedule: Vec<(Kind, f64, Instant)>,
+
+    pub stats: IcmpStats,
+}
+
+impl IcmpTraffic {
+    /// Add sockets for ICMP traffic from the local endpoints, every one of
+    /// them becomes an echo flow.
+    pub fn new(
+        config: Icmp,
+        locals: &[(IpAddress, u16)],
+        sockets: &mut SocketSet<'static>,
+    ) -> Result<Self, WorkerError> {
+        let Some(&(first, _)) = locals.first() else {
+            return Err(WorkerError::InternalWithMessage(String::from(
+                "icmp traffic needs at least one flow",
+            )));
+        };
+
+        let (version, protocol) = match first {
+            IpAddress::Ipv4(_) => (IpVersion::Ipv4, IpProtocol::Icmp),
+            IpAddress::Ipv6(_) => (IpVersion::Ipv6, IpProtocol::Icmpv6),
+        };
+
+        let icmp = sockets.add(raw_socket(version, protocol));
+        let raw = sockets
+            .add(raw_socket(version, IpProtocol::from(config.raw_protocol)));
+
+        // Identifiers only have to be unique within the worker
+        let base: u16 = thread_rng().r#gen();
+        let mut flows = Vec::with_capacity(locals.len());
+        for (i, &local) in locals.iter().enumerate() {
+            let ident = base.wrapping_add(i as u16);
+
+            let mut socket = icmp::Socket::new(
+                icmp::PacketBuffer::new(
+                    vec![icmp::PacketMetadata::EMPTY; 4],
+                    vec![0; 4 * (config.payload_size + 64)],
+                ),
+                icmp::PacketBuffer::new(vec![], vec![]),
+            );
+            socket.bind(icmp::Endpoint::Ident(ident)).map_err(|e| {
+                WorkerError::InternalWithMessage(format!(
+                    "cannot bind echo identifier {ident}, {e}"
+                ))
+            })?;
+
+            flows.push(Flow {
+                local,
+                ident,
+                seq_no: 0,
+                handle: sockets.add(socket),
+            });
+        }
+
+        let now = Instant::now();
+        let schedule = [
+            (Kind::Echo, config.echo_rate),
+            (Kind::Unreachable, config.unreachable_rate),
+            (Kind::Raw, config.raw_rate),
+        ]
+        .into_iter()
+        .filter(|(_, rate)| *rate > 0.0)
+        .map(|(kind, rate)| (kind, rate, now + interval(rate)))
+        .collect();
+
+        Ok(IcmpTraffic {
+            config,
+            icmp,
+            raw,
+            flows,
+            next_flow: 0,
+            schedule,
+            stats: IcmpStats::default(),
+        })
+    }
+
+    /// Whether the socket belongs to the ICMP traffic.
+    pub fn contains(&self, handle: SocketHandle) -> bool {
+        handle == self.icmp
+            || handle == self.raw
+            || self.flows.iter().any(|flow| flow.handle == handle)
+    }
+
+    /// Receive echo replies and send everything that is due.
+    pub fn poll(
+        &mut self,
+        now: Instant,
+        sockets: &mut SocketSet<'static>,
+        destinations: &mut Destinations,
+    ) {
+        for flow in &self.flows {
+            let socket = sockets.get_mut::<icmp::Socket>(flow.handle);
+            while let Ok((_data, remote)) = socket.recv() {
+                trace!("echo reply from {} to {}", remote, flow.local.0);
+                self.stats.echo_replies += 1;
+            }
+        }
+
+        for i in 0..self.schedule.len() {
+            let (kind, rate, mut due) = self.schedule[i];
+
+            while due <= now {
+                due += interval(rate);
+
+                let Some(remote) = destinations.pick(true) else {
+                    continue;
+                };
+
+                if let Err(e) = self.send(kind, remote, sockets) {
+                    // The tx buffer is full, do not try to catch up
+                    trace!("cannot send {:?}, {}", kind, e);
+                    due = now + interval(rate);
+                    break;
+                }
+            }
+
+            self.schedule[i].2 = due;
+        }
+    }
+
+    /// Time until the next packet is due.
+    pub fn poll_delay(&self, now: Instant) -> Option<Duration> {
+        self.schedule
+            .iter()
+            .map(|(_, _, due)| {
+                if *due > now {
+                    *due - now
+                } else {
+                    Duration::ZERO
+                }
+            })
+            .min()
+    }
+
+    fn send(
+        &mut self,
+        kind: Kind,
+        remote: (IpAddress, u16),
+        sockets: &mut SocketSet<'static>,
+    ) -> Result<(), raw::SendError> {
+        let next = (self.next_flow + 1) % self.flows.len();
+        let flow =
+            &mut self.flows[std::mem::replace(&mut self.next_flow, next)];
+
+        // Destinations of the other family are skipped
+        if flow.local.0.version() != remote.0.version() {
+            return Ok(());
+        }
+
+        let size = self.config.payload_size;
+        let (handle, packet) = match kind {
+            Kind::Echo => {
+                flow.seq_no = flow.seq_no.wrapping_add(1);
+                let packet = echo_request(
+                    flow.local.0,
+                    remote.0,
+                    flow.ident,
+                    flow.seq_no,
+                    &payload(size),
+                );
+                (self.icmp, packet)
+            }
+            Kind::Unreachable => (self.icmp, unreachable(flow.local, remote)),
+            Kind::Raw => {
+                let protocol = IpProtocol::from(self.config.raw_protocol);
+                let repr = IpRepr::new(
+                    flow.local.0,
+                    remote.0,
+                    protocol,
+                    size,
+                    HOP_LIMIT,
+                );
+                let data = payload(size);
+                (self.raw, ip_packet(repr, |buf| buf.copy_from_slice(&data)))
+            }
+        };
+
+        sockets.get_mut::<raw::Socket>(handle).send_slice(&packet)?;
+
+        match kind {
+            Kind::Echo => self.stats.echo_requests += 1,
+            Kind::Unreachable => self.stats.unreachable += 1,
+            Kind::Raw => self.stats.raw += 1,
+        }
+
+        Ok(())
+    }
+}
+
+fn raw_socket(
+    version: IpVersion,
+    protocol: IpProtocol,
+) -> raw::Socket<'static> {
+    // Send only, incoming packets are handled by icmp sockets
+    raw::Socket::new(
+        version,
+        protocol,
+        raw::PacketBuffer::new(vec![], vec![]),
+        raw::PacketBuffer::new(
+            vec![raw::PacketMetadata::EMPTY; 64],
+            vec![0; 64 * 1500],
+        ),
+    )
+}
+
+/// Waiting time until the next packet of a Poisson process with the rate.
+fn interval(rate: f64) -> Duration {
+    let secs: f64 = thread_rng().sample(Exp::new(rate).unwrap());
+    Duration::from_micros((secs * 1_000_000.0) as u64)
+}
+
+fn payload(size: usize) -> Vec<u8> {
+    (0..size).map(|_| thread_rng().r#gen()).collect()
+}
+
+/// IP packet with the header from the representation, and the payload
+/// filled by the closure.
+pub(crate) fn ip_packet(
+    repr: IpRepr,
+    emit_payload: impl FnOnce(&mut [u8]),
+) -> Vec<u8> {
+    let mut packet = vec![0; repr.buffer_len()];
+    repr.emit(&mut packet[..], &ChecksumCapabilities::default());
+    emit_payload(&mut packet[repr.header_len()..]);
+    packet
+}
+
+/// ICMP or ICMPv6 echo request from the spoofed source.
+pub(crate) fn echo_request(
+    src: IpAddress,
+    dst: IpAddress,
+    ident: u16,
+    seq_no: u16,
+    data: &[u8],
+) -> Vec<u8> {
+    let caps = ChecksumCapabilities::default();
+
+    match (src, dst) {
+        (IpAddress::Ipv4(src_addr), IpAddress::Ipv4(dst_addr)) => {
+            let icmp = Icmpv4Repr::EchoRequest {
+                ident,
+                seq_no,
+                data,
+            };
+            let repr = IpRepr::Ipv4(Ipv4Repr {
+                src_addr,
+                dst_addr,
+                next_header: IpProtocol::Icmp,
+                payload_len: icmp.buffer_len(),
+                hop_limit: HOP_LIMIT,
+            });
+            ip_packet(repr, |buf| {
+                icmp.emit(&mut Icmpv4Packet::new_unchecked(buf), &caps)
+            })
+        }
+        (IpAddress::Ipv6(src_addr), IpAddress::Ipv6(dst_addr)) => {
+            let icmp = Icmpv6Repr::EchoRequest {
+                ident,
+                seq_no,
+                data,
+            };
+            let repr = IpRepr::Ipv6(Ipv6Repr {
+                src_addr,
+                dst_addr,
+                next_header: IpProtocol::Icmpv6,
+                payload_len: icmp.buffer_len(),
+                hop_limit: HOP_LIMIT,
+            });
+            ip_packet(repr, |buf| {
+                icmp.emit(
+                    &src_addr,
+                    &dst_addr,
+                    &mut Icmpv6Packet::new_unchecked(buf),
+                    &caps,
+                )
+            })
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Port unreachable message from the spoofed client, reporting a UDP
+/// datagram from the remote endpoint to the client as undeliverable. Only
+/// the IP and UDP headers of the datagram are quoted.
+fn unreachable(local: (IpAddress, u16), remote: (IpAddress, u16)) -> Vec<u8> {
+    let caps = ChecksumCapabilities::default();
+
+    // Datagram of a random size with no checksum
+    let datagram_len = UDP_HEADER_LEN + thread_rng().gen_range(0..512);
+    let mut udp = [0u8; UDP_HEADER_LEN];
+    let mut datagram = UdpPacket::new_unchecked(&mut udp[..]);
+    datagram.set_src_port(remote.1);
+    datagram.set_dst_port(local.1);
+    datagram.set_len(datagram_len as u16);
+    datagram.set_checksum(0);
+
+    match (local.0, remote.0) {
+        (IpAddress::Ipv4(src_addr), IpAddress::Ipv4(dst_addr)) => {
+            let icmp = Icmpv4Repr::DstUnreachable {
+                reason: Icmpv4DstUnreachable::PortUnreachable,
+                header: Ipv4Repr {
+                    src_addr: dst_addr,
+                    dst_addr: src_addr,
+                    next_header: IpProtocol::Udp,
+                    payload_len: datagram_len,
+                    hop_limit: HOP_LIMIT,
+                },
+                data: &udp,
+            };
+            let repr = IpRepr::Ipv4(Ipv4Repr {
+                src_addr,
+                dst_addr,
+                next_header: IpProtocol::Icmp,
+                payload_len: icmp.buffer_len(),
+                hop_limit: HOP_LIMIT,
+            });
+            ip_packet(repr, |buf| {
+                icmp.emit(&mut Icmpv4Packet::new_unchecked(buf), &caps)
+            })
+        }
+        (IpAddress::Ipv6(src_addr), IpAddress::Ipv6(dst_addr)) => {
+            let icmp = Icmpv6Repr::DstUnreachable {
+                reason: Icmpv6DstUnreachable::PortUnreachable,
+                header: Ipv6Repr {
+                    src_addr: dst_addr,
+                    dst_addr: src_addr,
+                    next_header: IpProtocol::Udp,
+                    payload_len: datagram_len,
+                    hop_limit: HOP_LIMIT,
+                },
+                data: &udp,
+            };
+            let repr = IpRepr::Ipv6(Ipv6Repr {
+                src_addr,
+                dst_addr,
+                next_header: IpProtocol::Icmpv6,
+                payload_len: icmp.buffer_len(),
+                hop_limit: HOP_LIMIT,
+            });
+            ip_packet(repr, |buf| {
+                icmp.emit(
+                    &src_addr,
+                    &dst_addr,
+                    &mut Icmpv6Packet::new_unchecked(buf),
+                    &caps,
+                )
+            })
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smoltcp::wire::{
+        IPV4_HEADER_LEN, Icmpv4Message, Ipv4Packet, Ipv6Packet,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_echo_request() {
+        let caps = ChecksumCapabilities::default();
+        let (src, dst) =
+            ("10.0.0.5".parse().unwrap(), "10.0.0.1".parse().unwrap());
+
+        let packet = echo_request(src, dst, 7, 3, b"ping");
+        let ip = Ipv4Packet::new_checked(&packet[..]).unwrap();
+        assert!(ip.verify_checksum());
+        assert_eq!(IpAddress::Ipv4(ip.src_addr()), src);
+
+        let icmp = Icmpv4Packet::new_checked(ip.payload()).unwrap();
+        assert_eq!(
+            Icmpv4Repr::parse(&icmp, &caps).unwrap(),
+            Icmpv4Repr::EchoRequest {
+                ident: 7,
+                seq_no: 3,
+                data: b"ping",
+            }
+        );
+
+        let (src, dst) =
+            ("fd00::5".parse().unwrap(), "fd00::1".parse().unwrap());
+        let packet = echo_request(src, dst, 7, 3, b"ping");
+        let ip = Ipv6Packet::new_checked(&packet[..]).unwrap();
+        let (IpAddress::Ipv6(src), IpAddress::Ipv6(dst)) = (src, dst) else {
+            unreachable!()
+        };
+
+        let icmp = Icmpv6Packet::new_checked(ip.payload()).unwrap();
+        assert_eq!(
+            Icmpv6Repr::parse(&src, &dst, &icmp, &caps).unwrap(),
+            Icmpv6Repr::EchoRequest {
+                ident: 7,
+                seq_no: 3,
+                data: b"ping",
+            }
+        );
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let local = ("10.0.0.5".parse().unwrap(), 49152);
+        let remote = ("10.0.0.1".parse().unwrap(), 53);
+
+        let packet = unreachable(local, remote);
+        let ip = Ipv4Packet::new_checked(&packet[..]).unwrap();
+        assert_eq!(ip.next_header(), IpProtocol::Icmp);
+
+        let icmp = Icmpv4Packet::new_checked(ip.payload()).unwrap();
+        assert!(icmp.verify_checksum());
+        assert_eq!(icmp.msg_type(), Icmpv4Message::DstUnreachable);
+        assert_eq!(icmp.msg_code(), 3);
+
+        // The quoted datagram is truncated, which smoltcp refuses to parse
+        let quoted = Ipv4Packet::new_unchecked(icmp.data());
+        assert_eq!(IpAddress::Ipv4(quoted.src_addr()), remote.0);
+        assert_eq!(IpAddress::Ipv4(quoted.dst_addr()), local.0);
+        assert_eq!(quoted.next_header(), IpProtocol::Udp);
+
+        let datagram =
+            UdpPacket::new_unchecked(&icmp.data()[IPV4_HEADER_LEN..]);
+        assert_eq!(datagram.src_port(), remote.1);
+        assert_eq!(datagram.dst_port(), local.1);
+    }
+}
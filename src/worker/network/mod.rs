@@ -0,0 +1,1332 @@
+use core_affinity::CoreId;
+use log::{debug, info, trace, warn};
+use rand::distributions::WeightedIndex;
+use rand::{Rng, thread_rng};
+use rand_distr::{Distribution, Exp};
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{
+    fmt::Display,
+    net::{IpAddr, SocketAddr, TcpListener, UdpSocket},
+};
+
+use crate::{
+    BaseConfig, Capture, DeviceMedium, Faults, Protocol, Sharing, TunDevice,
+    Worker, WorkerError, Workload, WorkloadConfig, worker::raise_nofile_limit,
+};
+
+use self::{
+    blackhole::Blackhole,
+    capture::PcapFile,
+    destinations::Destinations,
+    icmp::IcmpTraffic,
+    protocols::{Application, Applications},
+    schedule::Schedule,
+    server::Server,
+};
+
+mod blackhole;
+mod capture;
+mod destinations;
+mod device;
+mod icmp;
+mod namespace;
+mod protocols;
+mod schedule;
+mod server;
+
+pub(crate) use self::icmp::{echo_request, ip_packet};
+
+use smoltcp::iface::{Config, Interface, SocketHandle, SocketSet};
+use smoltcp::phy::{
+    FaultInjector, PcapMode, PcapWriter, Tracer, TunTapInterface,
+    wait as phy_wait,
+};
+use smoltcp::socket::{Socket, tcp, udp};
+use smoltcp::time::Instant;
+use smoltcp::wire::{
+    HardwareAddress, IpAddress, IpCidr, Ipv4Address, Ipv6Address,
+};
+
+/// How long a closing connection could wait for the peer, before it's
+/// aborted. The same as the default of tcp_fin_timeout on Linux.
+const CLOSING_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Counters of the client side, reported periodically.
+#[derive(Debug, Default)]
+struct ClientStats {
+    messages: u64,
+    sent: u64,
+    received: u64,
+
+    /// Number of dynamic connections terminated in every way.
+    graceful: u64,
+    aborted: u64,
+    silent: u64,
+    handshake_timeouts: u64,
+}
+
+impl Display for ClientStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "messages {}, sent {}, received {}, graceful {}, aborted {}, silent {}, handshake timeouts {}",
+            self.messages,
+            self.sent,
+            self.received,
+            self.graceful,
+            self.aborted,
+            self.silent,
+            self.handshake_timeouts
+        )
+    }
+}
+
+/// How a dynamic connection is terminated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Termination {
+    Graceful,
+    Abort,
+    Silent,
+    HandshakeTimeout,
+}
+
+/// Dynamic connection, which is going to be terminated at the end of its
+/// lifetime.
+#[derive(Debug)]
+struct Flow {
+    opened: SystemTime,
+    lifetime: f64,
+    termination: Termination,
+    local: (IpAddress, u16),
+}
+
+/// Device stack of the client, from the tun or tap device up.
+type ClientDevice =
+    Blackhole<FaultInjector<Tracer<PcapWriter<TunTapInterface, PcapFile>>>>;
+
+pub struct NetworkWorker {
+    config: BaseConfig,
+    workload: WorkloadConfig,
+
+    /// Index of the worker among all of them, and their number. Used to
+    /// split the client address pool and devices between workers.
+    index: usize,
+    workers: usize,
+
+    /// Server sockets bound in advance, so that clients forked right after
+    /// the server could connect immediately.
+    bound: Option<(Option<TcpListener>, Option<UdpSocket>)>,
+}
+
+impl NetworkWorker {
+    pub fn new(workload: WorkloadConfig, cpu: CoreId, process: usize) -> Self {
+        let (index, workers) = worker_slot(&workload, cpu, process);
+
+        NetworkWorker {
+            config: BaseConfig { cpu, process },
+            workload,
+            index,
+            workers,
+            bound: None,
+        }
+    }
+
+    /// Start a simple server. The client side is going to be a networking
+    /// worker as well, so for convenience of troubleshooting do not error
+    /// out if something unexpected happened, log and proceed instead.
+    fn start_server(
+        &self,
+        addr: IpAddr,
+        target_port: u16,
+        protocol: Protocol,
+        bidirectional: bool,
+    ) -> Result<(), WorkerError> {
+        debug!("Starting server at {:?}:{:?}", addr, target_port);
+
+        raise_nofile_limit();
+
+        let (listener, udp) = match &self.bound {
+            Some((listener, udp)) => {
+                let clone_error = |e| {
+                    WorkerError::InternalWithMessage(format!(
+                        "cannot use bound sockets, {e}"
+                    ))
+                };
+
+                (
+                    listener
+                        .as_ref()
+                        .map(TcpListener::try_clone)
+                        .transpose()
+                        .map_err(clone_error)?,
+                    udp.as_ref()
+                        .map(UdpSocket::try_clone)
+                        .transpose()
+                        .map_err(clone_error)?,
+                )
+            }
+            None => bind(addr, target_port, protocol)?,
+        };
+
+        Server::new(listener, udp, bidirectional)
+            .and_then(|mut server| server.run(&self.config))
+            .map_err(|e| {
+                WorkerError::InternalWithMessage(format!("server failed, {e}"))
+            })
+    }
+
+    fn start_client(
+        &self,
+        pool: Pool,
+        target_port: u16,
+    ) -> Result<(), WorkerError> {
+        let Workload::Network {
+            server: _,
+            address: _,
+            prefix_len: _,
+            pool_start: _,
+            pool_size: _,
+            ref device,
+            namespace: _,
+            target_port: _,
+            destinations: ref configured,
+            popularity,
+            inbound,
+            protocol,
+            arrival_rate,
+            departure_rate,
+            connections_static,
+            connections_dyn_max,
+            conns_per_addr,
+            send_interval,
+            send_rate,
+            payload_size,
+            ref application,
+            bidirectional: _,
+            bulk,
+            buffer_size,
+            faults,
+            termination,
+            ref capture,
+            icmp,
+            preempt,
+        } = self.workload.workload
+        else {
+            unreachable!()
+        };
+
+        let addr = pool.cidr.address();
+        debug!("Starting client, target {:?}:{:?}", addr, target_port);
+
+        let (mut iface, mut device, fd) =
+            self.setup_tuntap(pool.cidr, device, faults, capture.as_ref())?;
+
+        let terminations = [
+            (Termination::Graceful, termination.graceful),
+            (Termination::Abort, termination.abort),
+            (Termination::Silent, termination.silent),
+            (Termination::HandshakeTimeout, termination.handshake_timeout),
+        ];
+        let mix = WeightedIndex::new(terminations.iter().map(|(_, w)| *w))
+            .map_err(|e| {
+                WorkerError::InternalWithMessage(format!(
+                    "invalid termination mix, {e}"
+                ))
+            })?;
+
+        // Dynamic sockets are going to be responsible for connections that
+        // will be opened/closed during the test. Every record maps socket
+        // handle (just an index inside smoltcp) to the flow, containing
+        // the time when the connection was opened, its lifetime and how it's
+        // going to be terminated.
+        let mut dynamic_sockets: HashMap<SocketHandle, Flow> = HashMap::new();
+
+        // Sockets terminated gracefully or aborted, which are kept until
+        // FIN or RST is sent, but not longer than the deadline
+        let mut closing = HashMap::new();
+
+        let mut destinations = Destinations::new(
+            configured,
+            popularity,
+            inbound,
+            (addr, target_port),
+        )?;
+
+        // Where every flow sends data to, and what protocol it speaks
+        let mut remotes = HashMap::new();
+        let applications = Applications::new(application)?;
+        let mut speaks = HashMap::new();
+
+        // Open static set of connections, that are going to live throughout
+        // the whole run
+        let mut sockets = SocketSet::new(vec![]);
+
+        for index in 0..connections_static {
+            let datagram = is_datagram(protocol, index);
+            let Some(remote) = destinations.pick(datagram) else {
+                warn!("No destination for static flow {}", index);
+                continue;
+            };
+
+            let (local_addr, local_port) =
+                get_local_addr_port(pool, conns_per_addr, index);
+            info!("connecting from {}:{}", local_addr, local_port);
+            let handle = open_flow(
+                &mut iface,
+                &mut sockets,
+                datagram,
+                (local_addr, local_port),
+                remote,
+                buffer_size,
+            )?;
+            remotes.insert(handle, remote);
+            speaks.insert(handle, applications.pick(datagram));
+        }
+
+        // ICMP and raw traffic is sent from the first client addresses,
+        // next to the static connections
+        let mut icmp = match icmp {
+            Some(config) => {
+                let locals: Vec<_> = (0..config.flows)
+                    .map(|index| {
+                        get_local_addr_port(pool, conns_per_addr, index)
+                    })
+                    .collect();
+                Some(IcmpTraffic::new(config, &locals, &mut sockets)?)
+            }
+            None => None,
+        };
+        let is_icmp = |icmp: &Option<IcmpTraffic>, h| {
+            icmp.as_ref().is_some_and(|i| i.contains(h))
+        };
+
+        // By default use global timer to throttle sending the data. It means
+        // there will be some irregularity about data sending betwen various
+        // connections, but to make it more precise we need to bookkeeping for
+        // every connection, which may waste memory and introduce unstability
+        // on its own.
+        let mut schedule = Schedule::new(send_interval, send_rate, bulk);
+
+        let mut stats = ClientStats::default();
+        let mut report = SystemTime::now();
+
+        // Timer and waiting interval for the next new dynamic connection
+        let mut arrivals = SystemTime::now();
+        let mut interval: f64 =
+            thread_rng().sample(Exp::new(arrival_rate).unwrap());
+
+        // Current number of opened connections, both dynamic and static
+        let mut total_conns = connections_static;
+
+        // The main loop, where connection state will be updated, and dynamic
+        // connections will be opened/closed
+        loop {
+            // Vector of sockets to close at the end of each loop
+            let mut close_sockets = vec![];
+
+            let timestamp = Instant::now();
+            iface.poll(timestamp, &mut device, &mut sockets);
+
+            if let Some(icmp) = &mut icmp {
+                icmp.poll(timestamp, &mut sockets, &mut destinations);
+            }
+
+            let elapsed = arrivals.elapsed().unwrap().as_millis();
+            if elapsed > (interval * 1000.0).round() as u128 {
+                // Time for a new connection, add a socket, it state is going
+                // to be updated during the next loop round
+                total_conns += 1;
+
+                let index = total_conns;
+                let (local_addr, local_port) =
+                    get_local_addr_port(pool, conns_per_addr, total_conns);
+
+                let lifetime: f64 =
+                    thread_rng().sample(Exp::new(departure_rate).unwrap());
+
+                // If we've reached the connections limit
+                if dynamic_sockets.len() == connections_dyn_max as usize
+                    && preempt
+                {
+                    let idx =
+                        thread_rng().gen_range(0..connections_dyn_max as usize);
+                    let (key, _) = sockets
+                        .iter()
+                        .filter(|(h, _)| !is_icmp(&icmp, *h))
+                        .nth(idx)
+                        .unwrap();
+                    dynamic_sockets.remove(&key);
+                    closing.remove(&key);
+                    close_sockets.push(key);
+                }
+
+                let datagram = is_datagram(protocol, index);
+                let remote = destinations.pick(datagram);
+                if remote.is_none() {
+                    debug!("No destination for a new flow");
+                }
+
+                // either we've just removed a socket and want to preempt
+                // or, we've have space and we're processing normally
+                if dynamic_sockets.len() < connections_dyn_max as usize
+                    && let Some(remote) = remote
+                {
+                    let local = (local_addr, local_port);
+
+                    // UDP flows have no way to terminate but to disappear
+                    let termination = if datagram {
+                        Termination::Silent
+                    } else {
+                        terminations[mix.sample(&mut thread_rng())].0
+                    };
+
+                    // The endpoint could be left over from a previous flow.
+                    // The SYN of a timing out handshake never leaves, so
+                    // that the server doesn't see the connection at all.
+                    device.remove(local);
+                    if termination == Termination::HandshakeTimeout {
+                        device.mute(local);
+                    }
+
+                    let handle = open_flow(
+                        &mut iface,
+                        &mut sockets,
+                        datagram,
+                        local,
+                        remote,
+                        buffer_size,
+                    )?;
+                    remotes.insert(handle, remote);
+                    speaks.insert(handle, applications.pick(datagram));
+                    dynamic_sockets.insert(
+                        handle,
+                        Flow {
+                            opened: SystemTime::now(),
+                            lifetime,
+                            termination,
+                            local,
+                        },
+                    );
+                }
+
+                info!(
+                    "New connecting from {}:{}, lifetime {}, index {}",
+                    local_addr,
+                    local_port,
+                    lifetime,
+                    index - 1
+                );
+
+                // set new interval for the next new connection
+                interval = thread_rng().sample(Exp::new(arrival_rate).unwrap());
+                arrivals = SystemTime::now();
+            }
+
+            // Iterate through all sockets, update the state for each one
+            for (i, (h, s)) in sockets.iter_mut().enumerate() {
+                if is_icmp(&icmp, h) {
+                    continue;
+                }
+
+                match s {
+                    Socket::Tcp(socket) => {
+                        info!("Process socket {}, {}", i, socket.state())
+                    }
+                    Socket::Udp(_) => info!("Process socket {}, UDP", i),
+                    _ => return Err(WorkerError::Internal),
+                }
+
+                if let Some(&deadline) = closing.get(&h) {
+                    // Wait until FIN or RST is sent, TIME-WAIT is not
+                    // interesting on the client side. If the peer never
+                    // finishes closing, the connection is aborted, and
+                    // dropped if even RST doesn't go out in time.
+                    if let Socket::Tcp(socket) = s {
+                        if socket.state() == tcp::State::TimeWait
+                            || socket.local_endpoint().is_none()
+                        {
+                            closing.remove(&h);
+                            close_sockets.push(h);
+                        } else if deadline <= SystemTime::now() {
+                            debug!("Closing socket {} expired", i);
+                            if socket.state() == tcp::State::Closed {
+                                closing.remove(&h);
+                                close_sockets.push(h);
+                            } else {
+                                socket.abort();
+                                closing.insert(
+                                    h,
+                                    SystemTime::now() + CLOSING_TIMEOUT,
+                                );
+                            }
+                        }
+                    }
+                    continue;
+                }
+
+                match dynamic_sockets.get(&h) {
+                    Some(flow) => {
+                        // A dynamic connection, verify lifetime
+                        debug!("Dynamic socket {}", i);
+                        if flow.opened.elapsed().unwrap().as_millis()
+                            > (flow.lifetime * 1000.0).round() as u128
+                        {
+                            info!("Close socket {}, {:?}", i, flow.termination);
+                            match (flow.termination, &mut *s) {
+                                (
+                                    Termination::Graceful,
+                                    Socket::Tcp(socket),
+                                ) => {
+                                    socket.close();
+                                    closing.insert(
+                                        h,
+                                        SystemTime::now() + CLOSING_TIMEOUT,
+                                    );
+                                    stats.graceful += 1;
+                                }
+                                (Termination::Abort, Socket::Tcp(socket)) => {
+                                    socket.abort();
+                                    closing.insert(
+                                        h,
+                                        SystemTime::now() + CLOSING_TIMEOUT,
+                                    );
+                                    stats.aborted += 1;
+                                }
+                                (Termination::HandshakeTimeout, _) => {
+                                    close_sockets.push(h);
+                                    stats.handshake_timeouts += 1;
+                                }
+                                _ => {
+                                    // Do not answer the peer with RST after
+                                    // disappearing
+                                    device.insert(flow.local);
+                                    close_sockets.push(h);
+                                    stats.silent += 1;
+                                }
+                            }
+                            dynamic_sockets.remove(&h);
+                            continue;
+                        }
+                    }
+                    None => {
+                        // Static connection, continue
+                        debug!("Static socket {}", i);
+                    }
+                }
+
+                match s {
+                    Socket::Tcp(socket) => {
+                        if socket.can_recv() {
+                            let received = socket
+                                .recv(|data| {
+                                    trace!(
+                                        "{}",
+                                        str::from_utf8(data)
+                                            .unwrap_or("(invalid utf8)")
+                                    );
+                                    (data.len(), data.len())
+                                })
+                                .unwrap();
+                            stats.received += received as u64;
+                        }
+
+                        if socket.may_send() && schedule.due(h) {
+                            loop {
+                                let data = speaks
+                                    .get(&h)
+                                    .unwrap_or(&Application::Hello)
+                                    .request(i, payload_size, buffer_size);
+                                let room = socket.send_capacity()
+                                    - socket.send_queue();
+
+                                // Do not split messages, if there is no room
+                                // the message is skipped
+                                if room < data.len() {
+                                    break;
+                                }
+
+                                trace!(
+                                    "sending request from idx {} addr {}, len {}",
+                                    i,
+                                    socket.local_endpoint().unwrap().addr,
+                                    data.len()
+                                );
+                                socket.send_slice(&data).expect("cannot send");
+                                stats.messages += 1;
+                                stats.sent += data.len() as u64;
+
+                                if !bulk {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Socket::Udp(socket) => {
+                        while let Ok((data, _meta)) = socket.recv() {
+                            trace!(
+                                "{}",
+                                str::from_utf8(data)
+                                    .unwrap_or("(invalid utf8)")
+                            );
+                            stats.received += data.len() as u64;
+                        }
+
+                        if socket.can_send() && schedule.due(h) {
+                            loop {
+                                let data = speaks
+                                    .get(&h)
+                                    .unwrap_or(&Application::Hello)
+                                    .request(i, payload_size, buffer_size);
+                                trace!(
+                                    "sending datagram from idx {} addr {:?}, len {}",
+                                    i,
+                                    socket.endpoint().addr,
+                                    data.len()
+                                );
+
+                                let Some(remote) = remotes.get(&h) else {
+                                    break;
+                                };
+
+                                if let Err(e) =
+                                    socket.send_slice(&data, *remote)
+                                {
+                                    trace!("cannot send datagram, {}", e);
+                                    break;
+                                }
+
+                                stats.messages += 1;
+                                stats.sent += data.len() as u64;
+
+                                if !bulk {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+            }
+
+            for h in close_sockets {
+                info!("Close handle {}", h);
+                // TODO: reuse sockets
+                sockets.remove(h);
+                schedule.remove(h);
+                closing.remove(&h);
+                remotes.remove(&h);
+                speaks.remove(&h);
+                total_conns -= 1;
+            }
+
+            info!("Sockets: {}", total_conns);
+
+            if report.elapsed().unwrap().as_secs() > 10 {
+                info!(
+                    "{}-{}: Flows {}, {}",
+                    self.config.cpu.id, self.config.process, total_conns, stats
+                );
+                if let Some(icmp) = &icmp {
+                    info!(
+                        "{}-{}: ICMP {}",
+                        self.config.cpu.id, self.config.process, icmp.stats
+                    );
+                }
+                report = SystemTime::now();
+            }
+
+            // We cant wait only for iface.poll_delay(timestamp, &sockets)
+            // interval, since the loop could stuck without any activity
+            // making no progress. To prevent that specify a minimum waiting
+            // duration of 100 milliseconds. In the bulk mode there is always
+            // something to send, so do not wait at all.
+            let min_duration = if bulk {
+                smoltcp::time::Duration::ZERO
+            } else {
+                smoltcp::time::Duration::from_millis(100)
+            };
+
+            let mut duration = iface
+                .poll_delay(timestamp, &sockets)
+                .min(Some(min_duration))
+                .or(Some(min_duration));
+
+            // Wake up in time for the next flow to send
+            if let Some(delay) = schedule.poll_delay() {
+                duration = duration.min(Some(delay.into()));
+            }
+
+            // Wake up in time for the next ICMP packet
+            if let Some(delay) =
+                icmp.as_ref().and_then(|i| i.poll_delay(timestamp))
+            {
+                duration = duration.min(Some(delay));
+            }
+
+            info!("wait duration {:?}", duration);
+            phy_wait(fd, duration).expect("wait error");
+        }
+    }
+
+    /// Setup a tun or tap device for communication, wrapped into a
+    /// PcapWriter, a Tracer, a FaultInjector configured with the specified
+    /// faults and a Blackhole. A shared device is attached to via its own
+    /// queue, if there are other workers.
+    fn setup_tuntap(
+        &self,
+        cidr: IpCidr,
+        tun: &TunDevice,
+        faults: Faults,
+        capture: Option<&Capture>,
+    ) -> Result<(Interface, ClientDevice, i32), WorkerError> {
+        let device_name = device::device_name(tun, self.index)?;
+        let multi_queue = tun.sharing == Sharing::Shared && self.workers > 1;
+        info!("Attaching to {device_name}, multi queue {multi_queue}");
+
+        let device = device::open(&device_name, tun.medium, multi_queue)?;
+        let fd = device.as_raw_fd();
+
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .subsec_nanos();
+
+        let pcap = PcapFile::new(capture, self.index, self.workers)?;
+        let device = PcapWriter::new(device, pcap, PcapMode::Both);
+
+        let device = Tracer::new(device, |_timestamp, printer| {
+            trace!("{}", printer);
+        });
+
+        let mut device = FaultInjector::new(device, seed);
+        device.set_drop_chance(faults.drop_chance);
+        device.set_corrupt_chance(faults.corrupt_chance);
+        device.set_max_packet_size(faults.max_packet_size);
+        device.set_max_tx_rate(faults.max_tx_rate);
+        device.set_max_rx_rate(faults.max_rx_rate);
+        device.set_bucket_interval(smoltcp::time::Duration::from_millis(
+            faults.bucket_interval,
+        ));
+
+        // Create interface
+        let mut config = match tun.medium {
+            DeviceMedium::Tun => Config::new(HardwareAddress::Ip),
+            DeviceMedium::Tap => Config::new(device::generate_mac().into()),
+        };
+        config.random_seed = rand::random();
+
+        let mut iface = Interface::new(config, &mut device, Instant::now());
+        iface.set_any_ip(true);
+        iface.update_ip_addrs(|ip_addrs| {
+            ip_addrs.push(cidr).unwrap();
+        });
+
+        match cidr.address() {
+            IpAddress::Ipv4(addr) => {
+                iface.routes_mut().add_default_ipv4_route(addr).unwrap();
+            }
+            IpAddress::Ipv6(addr) => {
+                iface.routes_mut().add_default_ipv6_route(addr).unwrap();
+            }
+        };
+
+        Ok((iface, Blackhole::new(device), fd))
+    }
+}
+
+/// In the namespace mode, move into a new network namespace with the client
+/// device configured, and return the server worker to run there as well.
+pub fn prepare(
+    workload: &WorkloadConfig,
+) -> Result<Vec<Box<dyn Worker>>, WorkerError> {
+    let Workload::Network {
+        server,
+        namespace,
+        address,
+        prefix_len,
+        ref device,
+        target_port,
+        protocol,
+        ..
+    } = workload.workload
+    else {
+        unreachable!()
+    };
+
+    if server || !namespace {
+        return Ok(vec![]);
+    }
+
+    if device.sharing == Sharing::PerWorker {
+        return Err(WorkerError::InternalWithMessage(String::from(
+            "a device per worker is not supported in the namespace mode",
+        )));
+    }
+
+    let prefix_len = client_prefix_len(address, prefix_len)?;
+    let (_, workers) = worker_slot(workload, CoreId { id: 0 }, 0);
+    namespace::setup(
+        &device.name,
+        device.medium,
+        workers > 1,
+        address,
+        prefix_len,
+    )?;
+
+    let mut config = workload.clone();
+    if let Workload::Network { server, .. } = &mut config.workload {
+        *server = true;
+    }
+
+    // Bind before any worker is forked, otherwise clients could connect
+    // before the server is listening, and get reset
+    let mut worker = NetworkWorker::new(config, CoreId { id: 0 }, 0);
+    worker.bound = Some(bind(address, target_port, protocol)?);
+
+    Ok(vec![Box::new(worker)])
+}
+
+/// Bind server sockets for the protocol.
+fn bind(
+    addr: IpAddr,
+    port: u16,
+    protocol: Protocol,
+) -> Result<(Option<TcpListener>, Option<UdpSocket>), WorkerError> {
+    let local = SocketAddr::new(addr, port);
+    let bind_error = |e| {
+        WorkerError::InternalWithMessage(format!(
+            "cannot listen on {local}, {e}"
+        ))
+    };
+
+    let listener = match protocol {
+        Protocol::Tcp | Protocol::Both => {
+            Some(TcpListener::bind(local).map_err(bind_error)?)
+        }
+        Protocol::Udp => None,
+    };
+
+    let udp = match protocol {
+        Protocol::Udp | Protocol::Both => {
+            Some(UdpSocket::bind(local).map_err(bind_error)?)
+        }
+        Protocol::Tcp => None,
+    };
+
+    Ok((listener, udp))
+}
+
+/// Index of the worker running on the cpu among all of them, and their
+/// number.
+fn worker_slot(
+    workload: &WorkloadConfig,
+    cpu: CoreId,
+    process: usize,
+) -> (usize, usize) {
+    if workload.per_core {
+        let cores = core_affinity::get_core_ids().unwrap_or_default();
+        let core = cores.iter().position(|c| c.id == cpu.id).unwrap_or(0);
+        (
+            core * workload.workers + process,
+            cores.len().max(1) * workload.workers,
+        )
+    } else {
+        (process, workload.workers)
+    }
+}
+
+/// Prefix length of the client network, 16 for IPv4 and 64 for IPv6 by
+/// default.
+fn client_prefix_len(
+    address: IpAddr,
+    prefix_len: Option<u8>,
+) -> Result<u8, WorkerError> {
+    let (width, default) = match address {
+        IpAddr::V4(_) => (32, 16),
+        IpAddr::V6(_) => (128, 64),
+    };
+
+    let prefix_len = prefix_len.unwrap_or(default);
+    if prefix_len > width {
+        return Err(WorkerError::InternalWithMessage(format!(
+            "invalid prefix length {prefix_len} for {address}"
+        )));
+    }
+
+    Ok(prefix_len)
+}
+
+/// Open a new flow from the local endpoint to the remote one. TCP sockets
+/// are connected, while UDP sockets are only bound to the local endpoint,
+/// the flow starts with the first datagram sent.
+fn open_flow(
+    iface: &mut Interface,
+    sockets: &mut SocketSet<'static>,
+    datagram: bool,
+    local: (IpAddress, u16),
+    remote: (IpAddress, u16),
+    buffer_size: usize,
+) -> Result<SocketHandle, WorkerError> {
+    if datagram {
+        let udp_rx_buffer = udp::PacketBuffer::new(
+            vec![udp::PacketMetadata::EMPTY; 16],
+            vec![0; buffer_size],
+        );
+        let udp_tx_buffer = udp::PacketBuffer::new(
+            vec![udp::PacketMetadata::EMPTY; 16],
+            vec![0; buffer_size],
+        );
+        let mut socket = udp::Socket::new(udp_rx_buffer, udp_tx_buffer);
+
+        socket.bind(local).map_err(|e| {
+            WorkerError::InternalWithMessage(format!(
+                "cannot bind {}:{}, {e}",
+                local.0, local.1
+            ))
+        })?;
+
+        Ok(sockets.add(socket))
+    } else {
+        let tcp_rx_buffer = tcp::SocketBuffer::new(vec![0; buffer_size]);
+        let tcp_tx_buffer = tcp::SocketBuffer::new(vec![0; buffer_size]);
+        let mut socket = tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer);
+
+        socket
+            .connect(iface.context(), remote, local)
+            .map_err(|e| {
+                WorkerError::InternalWithMessage(format!(
+                    "cannot connect from {}:{}, {e}",
+                    local.0, local.1
+                ))
+            })?;
+
+        Ok(sockets.add(socket))
+    }
+}
+
+/// Whether the flow with the specified index is UDP. With both protocols
+/// flows alternate between TCP and UDP.
+fn is_datagram(protocol: Protocol, index: u32) -> bool {
+    match protocol {
+        Protocol::Tcp => false,
+        Protocol::Udp => true,
+        Protocol::Both => !index.is_multiple_of(2),
+    }
+}
+
+/// Map socket index to a local port and address. The address is
+/// incremented every conns_per_addr sockets, whithin this interval the local
+/// port is incremented. The first port to be taken is 49152, an out of blue
+/// large enough number.
+///
+/// E.g. if the base address is 10.0.0.1, we put 100 connections on the same
+/// address and this is 10th connection. In this case we should get
+/// 10.0.0.2:49162 as a result, as if we were inserting 10 new addresses
+/// starting from 10.0.0.2 (remember, 10.0.0.1 is the base address and is
+/// already claimed) incrementing first 100 times the port, then the address.
+///
+/// pool - client addresses, either IPv4 or IPv6. The new address is going to
+///         be based on the cidr address plus the pool offset and the
+///         connection number, wrapping around within the pool and prefix.
+///
+/// conns_per_addr - how many connections are going to share the same IP
+///         address, and differ only in port value.
+///
+/// index - current global number of the connection.
+fn get_local_addr_port(
+    pool: Pool,
+    conns_per_addr: u16,
+    index: u32,
+) -> (IpAddress, u16) {
+    let local_port = 49152 + (index % conns_per_addr as u32) as u16;
+    debug!("addr {}, index {}", pool.cidr, index);
+
+    // conns_per_addr effectively groups connections together, one address per
+    // group with only port being different. addr_index represent current index
+    // inside the space of such groups.
+    let mut addr_index = (index / conns_per_addr as u32) as u128;
+    if let Some(size) = pool.size {
+        addr_index %= size;
+    }
+
+    let offset = pool.first.wrapping_add(addr_index);
+    let prefix_len = pool.cidr.prefix_len();
+
+    let local_addr = match pool.cidr.address() {
+        IpAddress::Ipv4(addr) => {
+            let bits = addr.to_bits() as u128;
+            let host = next_host(bits, 32, prefix_len, offset);
+            IpAddress::Ipv4(Ipv4Address::from_bits(host as u32))
+        }
+        IpAddress::Ipv6(addr) => {
+            let bits = addr.to_bits();
+            let host = next_host(bits, 128, prefix_len, offset);
+            IpAddress::Ipv6(Ipv6Address::from_bits(host))
+        }
+    };
+
+    (local_addr, local_port)
+}
+
+/// Client addresses, `size` of them starting `first` addresses after the
+/// cidr address. Without the size the whole prefix is used.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Pool {
+    cidr: IpCidr,
+    first: u128,
+    size: Option<u128>,
+}
+
+impl Pool {
+    /// Pool starting at the specified address, or right after the cidr
+    /// address by default.
+    fn new(
+        cidr: IpCidr,
+        start: Option<IpAddr>,
+        size: Option<u64>,
+    ) -> Result<Self, WorkerError> {
+        let first = match start {
+            None => 1,
+            Some(start) => {
+                let start = IpAddress::from(start);
+                if !cidr.contains_addr(&start) {
+                    return Err(WorkerError::InternalWithMessage(format!(
+                        "pool start {start} is outside of {cidr}"
+                    )));
+                }
+
+                to_bits(start).wrapping_sub(to_bits(cidr.address()))
+                    & host_mask(cidr)
+            }
+        };
+
+        if size == Some(0) {
+            return Err(WorkerError::InternalWithMessage(String::from(
+                "pool has to contain at least one address",
+            )));
+        }
+
+        Ok(Pool {
+            cidr,
+            first,
+            size: size.map(u128::from),
+        })
+    }
+
+    /// Part of the pool for the worker with the index, the pool is split
+    /// evenly between all of them.
+    fn partition(
+        self,
+        index: usize,
+        workers: usize,
+    ) -> Result<Self, WorkerError> {
+        if workers <= 1 {
+            return Ok(self);
+        }
+
+        let size = self.size.unwrap_or(host_mask(self.cidr).saturating_add(1));
+        let part = size / workers as u128;
+        if part == 0 {
+            return Err(WorkerError::InternalWithMessage(format!(
+                "pool of {size} addresses is too small for {workers} workers"
+            )));
+        }
+
+        Ok(Pool {
+            cidr: self.cidr,
+            first: self.first.wrapping_add(part * index as u128),
+            size: Some(part),
+        })
+    }
+}
+
+fn to_bits(addr: IpAddress) -> u128 {
+    match addr {
+        IpAddress::Ipv4(addr) => addr.to_bits() as u128,
+        IpAddress::Ipv6(addr) => addr.to_bits(),
+    }
+}
+
+/// Mask of the host part of cidr addresses.
+fn host_mask(cidr: IpCidr) -> u128 {
+    let width = match cidr.address() {
+        IpAddress::Ipv4(_) => 32,
+        IpAddress::Ipv6(_) => 128,
+    };
+
+    u128::MAX
+        .checked_shr(128 - width + cidr.prefix_len() as u32)
+        .unwrap_or(0)
+}
+
+/// Add the offset to the address bits, keeping the network part of an
+/// address with the specified width intact. Wrapping around within the
+/// prefix never gives the address itself, which belongs to the interface,
+/// and for IPv4 neither the network nor the broadcast address. The offset 0
+/// wraps around to the last host before the address.
+fn next_host(addr: u128, width: u32, prefix_len: u8, offset: u128) -> u128 {
+    let host_bits = width - prefix_len as u32;
+
+    // The whole 128 bit space, nothing to preserve
+    let Some(size) = 1u128.checked_shl(host_bits) else {
+        return addr.wrapping_add(offset.max(1));
+    };
+
+    let network = addr & !(size - 1);
+    let base = addr - network;
+
+    // Hosts to skip relative to the address, /31 has no network or
+    // broadcast addresses
+    let mut skipped: Vec<u128> = if width == 32 && size > 2 {
+        vec![(size - base) % size, (size - 1 - base) % size]
+    } else {
+        vec![]
+    };
+    skipped.retain(|host| *host != 0);
+    skipped.sort();
+    skipped.dedup();
+
+    let hosts = size - 1 - skipped.len() as u128;
+    if hosts == 0 {
+        return addr;
+    }
+
+    let mut host = match offset % hosts {
+        0 => hosts,
+        host => host,
+    };
+    for skip in skipped {
+        if skip <= host {
+            host += 1;
+        }
+    }
+
+    network | ((base + host) % size)
+}
+
+impl Worker for NetworkWorker {
+    fn run_payload(&self) -> Result<(), WorkerError> {
+        info!("{self}");
+
+        let Workload::Network {
+            server,
+            address,
+            prefix_len,
+            pool_start,
+            pool_size,
+            target_port,
+            protocol,
+            bidirectional,
+            faults,
+            ..
+        } = self.workload.workload
+        else {
+            unreachable!()
+        };
+
+        if server {
+            let _ = self.start_server(
+                address,
+                target_port,
+                protocol,
+                bidirectional,
+            );
+        } else {
+            let prefix_len = client_prefix_len(address, prefix_len)?;
+
+            if faults.drop_chance > 100 || faults.corrupt_chance > 100 {
+                return Err(WorkerError::InternalWithMessage(String::from(
+                    "fault chances have to be in percents",
+                )));
+            }
+
+            let cidr = IpCidr::new(IpAddress::from(address), prefix_len);
+            let pool = Pool::new(cidr, pool_start, pool_size)?
+                .partition(self.index, self.workers)?;
+            let _ = self.start_client(pool, target_port);
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for NetworkWorker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_local_addr_port() {
+        let test_cases = vec![
+            // (cidr, conns_per_addr, index, expected_ip, expected_port)
+            //
+            // 10 conns per group, 15 -> second group, increment = 2
+            (
+                IpCidr::new(IpAddress::v4(192, 168, 1, 100), 8),
+                10,
+                15,
+                IpAddress::v4(192, 168, 1, 102),
+                49157,
+            ),
+            // 9 conns per group, 15 -> second group, increment = 2
+            (
+                IpCidr::new(IpAddress::v4(192, 168, 1, 255), 8),
+                9,
+                15,
+                IpAddress::v4(192, 168, 2, 1),
+                49158,
+            ),
+            // 12 conns per group, 15 -> second group, increment = 2, the
+            // whole address space as a prefix
+            (
+                IpCidr::new(IpAddress::v4(192, 255, 255, 255), 0),
+                12,
+                15,
+                IpAddress::v4(193, 0, 0, 1),
+                49155,
+            ),
+            // 1 conn per group, 512 -> 512 group, increment = 512
+            (
+                IpCidr::new(IpAddress::v4(192, 168, 1, 100), 8),
+                1,
+                512,
+                IpAddress::v4(192, 168, 3, 101),
+                49152,
+            ),
+            // 1 conn per group, 65636 -> 65636 group, increment = 65636
+            (
+                IpCidr::new(IpAddress::v4(192, 168, 1, 100), 8),
+                1,
+                65636,
+                IpAddress::v4(192, 169, 1, 201),
+                49152,
+            ),
+            // 100 conn per group, 1 ->  group, increment = 1
+            (
+                IpCidr::new(IpAddress::v4(10, 0, 0, 1), 8),
+                100,
+                1,
+                IpAddress::v4(10, 0, 0, 2),
+                49153,
+            ),
+            // 1 conn per group, 2 -> second group, increment = 3, wraps
+            // around within /24 skipping the broadcast and network addresses
+            (
+                IpCidr::new(IpAddress::v4(10, 0, 0, 254), 24),
+                1,
+                2,
+                IpAddress::v4(10, 0, 0, 3),
+                49152,
+            ),
+            // 1 conn per group, 5 -> fifth group, the only host within /30
+            // which is neither the network, broadcast or base address
+            (
+                IpCidr::new(IpAddress::v4(10, 0, 0, 1), 30),
+                1,
+                5,
+                IpAddress::v4(10, 0, 0, 2),
+                49152,
+            ),
+            // 1 conn per group, 1 -> first group, /31 has no network or
+            // broadcast addresses
+            (
+                IpCidr::new(IpAddress::v4(10, 0, 0, 1), 31),
+                1,
+                1,
+                IpAddress::v4(10, 0, 0, 0),
+                49152,
+            ),
+            // 10 conns per group, 15 -> second group, increment = 2
+            (
+                IpCidr::new(IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 1), 64),
+                10,
+                15,
+                IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 3),
+                49157,
+            ),
+            // 1 conn per group, 65536 -> 65536 group, increment = 65537
+            (
+                IpCidr::new(IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 1), 64),
+                1,
+                65536,
+                IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 1, 2),
+                49152,
+            ),
+            // 1 conn per group, 1 -> first group, increment = 2, wraps
+            // around within /127 to the only address besides the base one
+            (
+                IpCidr::new(IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 1), 127),
+                1,
+                1,
+                IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 0),
+                49152,
+            ),
+        ];
+
+        for (cidr, conns_per_addr, index, expected_ip, expected_port) in
+            test_cases
+        {
+            let pool = Pool::new(cidr, None, None).unwrap();
+            let (ip, port) = get_local_addr_port(pool, conns_per_addr, index);
+            assert_eq!(ip, expected_ip);
+            assert_eq!(port, expected_port);
+        }
+    }
+
+    #[test]
+    fn test_pool() {
+        let cidr = IpCidr::new(IpAddress::v4(10, 0, 0, 1), 16);
+        let start = Some("10.0.1.0".parse().unwrap());
+
+        // 256 addresses starting from 10.0.1.0, wrapping around within them
+        let pool = Pool::new(cidr, start, Some(256)).unwrap();
+        assert_eq!(
+            get_local_addr_port(pool, 1, 0).0,
+            IpAddress::v4(10, 0, 1, 0)
+        );
+        assert_eq!(
+            get_local_addr_port(pool, 1, 257).0,
+            IpAddress::v4(10, 0, 1, 1)
+        );
+
+        // the second half of them for the second out of two workers
+        let part = pool.partition(1, 2).unwrap();
+        assert_eq!(part.size, Some(128));
+        assert_eq!(
+            get_local_addr_port(part, 1, 0).0,
+            IpAddress::v4(10, 0, 1, 128)
+        );
+        assert_eq!(
+            get_local_addr_port(part, 1, 128).0,
+            IpAddress::v4(10, 0, 1, 128)
+        );
+
+        // the whole /24 prefix split between four workers
+        let cidr = IpCidr::new(IpAddress::v4(10, 0, 0, 1), 24);
+        let part = Pool::new(cidr, None, None).unwrap().partition(1, 4);
+        assert_eq!(
+            get_local_addr_port(part.unwrap(), 1, 0).0,
+            IpAddress::v4(10, 0, 0, 66)
+        );
+
+        // the whole IPv6 space is large enough for everyone
+        let cidr = IpCidr::new(IpAddress::v6(0xfd00, 0, 0, 0, 0, 0, 0, 1), 0);
+        let part = Pool::new(cidr, None, None).unwrap().partition(3, 4);
+        assert!(part.is_ok());
+
+        assert!(Pool::new(cidr, None, Some(0)).is_err());
+        assert!(
+            Pool::new(IpCidr::new(IpAddress::v4(10, 0, 0, 1), 24), start, None)
+                .is_err()
+        );
+        assert!(pool.partition(0, 512).is_err());
+    }
+}
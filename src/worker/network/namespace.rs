@@ -0,0 +1,216 @@
+//! Self-contained mode of the network workload: a network namespace with the
+//! client device configured inside, so that neither a prepared device nor
+//! firewall tweaks are needed. The namespace with everything in it is gone
+//! once the last berserker process exits.
+
+use std::{io, net::IpAddr, os::fd::RawFd};
+
+use log::info;
+use nix::{
+    net::if_::if_nametoindex,
+    sched::{CloneFlags, unshare},
+    sys::socket::{
+        AddressFamily, MsgFlags, SockFlag, SockProtocol, SockType, recv, send,
+        socket,
+    },
+    unistd::close,
+};
+
+use crate::{DeviceMedium, WorkerError};
+
+use super::device;
+
+/// Size of nlmsghdr.
+const HEADER_LEN: usize = 16;
+
+/// Move berserker into a new network namespace, create the device there,
+/// assign the address to it and bring it up.
+pub(super) fn setup(
+    name: &str,
+    medium: DeviceMedium,
+    multi_queue: bool,
+    address: IpAddr,
+    prefix_len: u8,
+) -> Result<(), WorkerError> {
+    unshare(CloneFlags::CLONE_NEWNET).map_err(|e| {
+        WorkerError::InternalWithMessage(format!(
+            "failed to create network namespace, {e}"
+        ))
+    })?;
+
+    device::create(name, medium, multi_queue)?;
+
+    let netlink = Netlink::new()?;
+    netlink.request(&link_up(index_of("lo")?))?;
+
+    let index = index_of(name)?;
+    netlink.request(&add_address(index, address, prefix_len))?;
+    netlink.request(&link_up(index))?;
+
+    info!("Created {name} with {address}/{prefix_len} in a new namespace");
+    Ok(())
+}
+
+fn index_of(name: &str) -> Result<u32, WorkerError> {
+    if_nametoindex(name).map_err(|e| {
+        WorkerError::InternalWithMessage(format!(
+            "failed to find device {name}, {e}"
+        ))
+    })
+}
+
+/// Route netlink socket, sending one request at a time.
+struct Netlink {
+    fd: RawFd,
+}
+
+impl Netlink {
+    fn new() -> Result<Self, WorkerError> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkRoute,
+        )
+        .map_err(|e| {
+            WorkerError::InternalWithMessage(format!(
+                "failed to open netlink socket, {e}"
+            ))
+        })?;
+
+        Ok(Netlink { fd })
+    }
+
+    /// Send the request and wait for the acknowledgement.
+    fn request(&self, message: &[u8]) -> Result<(), WorkerError> {
+        let error = |e: io::Error| {
+            WorkerError::InternalWithMessage(format!(
+                "netlink request failed, {e}"
+            ))
+        };
+
+        send(self.fd, message, MsgFlags::empty())
+            .map_err(|e| error(e.into()))?;
+
+        let mut buf = [0u8; 4096];
+        let len = recv(self.fd, &mut buf, MsgFlags::empty())
+            .map_err(|e| error(e.into()))?;
+
+        match acknowledgement(&buf[..len]) {
+            Some(0) => Ok(()),
+            Some(errno) => Err(error(io::Error::from_raw_os_error(-errno))),
+            None => Err(error(io::Error::from(io::ErrorKind::InvalidData))),
+        }
+    }
+}
+
+impl Drop for Netlink {
+    fn drop(&mut self) {
+        let _ = close(self.fd);
+    }
+}
+
+/// Error code from the NLMSG_ERROR reply, zero means success.
+fn acknowledgement(reply: &[u8]) -> Option<i32> {
+    let kind = u16::from_ne_bytes(reply.get(4..6)?.try_into().ok()?);
+    if kind != libc::NLMSG_ERROR as u16 {
+        return None;
+    }
+
+    let errno = reply.get(HEADER_LEN..HEADER_LEN + 4)?;
+    Some(i32::from_ne_bytes(errno.try_into().ok()?))
+}
+
+/// Netlink request with the header in front of the body.
+fn message(kind: u16, flags: u16, body: &[u8]) -> Vec<u8> {
+    let flags = flags | (libc::NLM_F_REQUEST | libc::NLM_F_ACK) as u16;
+
+    let mut message = Vec::with_capacity(HEADER_LEN + body.len());
+    message.extend(((HEADER_LEN + body.len()) as u32).to_ne_bytes());
+    message.extend(kind.to_ne_bytes());
+    message.extend(flags.to_ne_bytes());
+    // Sequence number and port id, only one request is in flight
+    message.extend(1u32.to_ne_bytes());
+    message.extend(0u32.to_ne_bytes());
+    message.extend(body);
+    message
+}
+
+/// Route attribute, padded to 4 bytes.
+fn attribute(kind: u16, data: &[u8]) -> Vec<u8> {
+    let mut attribute = Vec::new();
+    attribute.extend(((4 + data.len()) as u16).to_ne_bytes());
+    attribute.extend(kind.to_ne_bytes());
+    attribute.extend(data);
+    attribute.resize(attribute.len().next_multiple_of(4), 0);
+    attribute
+}
+
+/// RTM_NEWLINK setting IFF_UP on the device.
+fn link_up(index: u32) -> Vec<u8> {
+    let up = (libc::IFF_UP as u32).to_ne_bytes();
+
+    // struct ifinfomsg
+    let mut body = vec![libc::AF_UNSPEC as u8, 0];
+    body.extend(0u16.to_ne_bytes());
+    body.extend(index.to_ne_bytes());
+    body.extend(up);
+    body.extend(up);
+
+    message(libc::RTM_NEWLINK, 0, &body)
+}
+
+/// RTM_NEWADDR assigning the address to the device. IPv6 addresses skip
+/// duplicate address detection to be usable right away.
+fn add_address(index: u32, address: IpAddr, prefix_len: u8) -> Vec<u8> {
+    let (family, flags, bytes) = match address {
+        IpAddr::V4(addr) => (libc::AF_INET, 0, addr.octets().to_vec()),
+        IpAddr::V6(addr) => {
+            (libc::AF_INET6, libc::IFA_F_NODAD, addr.octets().to_vec())
+        }
+    };
+
+    // struct ifaddrmsg
+    let mut body = vec![family as u8, prefix_len, flags as u8, 0];
+    body.extend(index.to_ne_bytes());
+    body.extend(attribute(libc::IFA_LOCAL, &bytes));
+    body.extend(attribute(libc::IFA_ADDRESS, &bytes));
+
+    message(
+        libc::RTM_NEWADDR,
+        (libc::NLM_F_CREATE | libc::NLM_F_EXCL) as u16,
+        &body,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_address() {
+        let message = add_address(3, "10.0.0.1".parse().unwrap(), 16);
+
+        // header, ifaddrmsg and two attributes
+        assert_eq!(message.len(), HEADER_LEN + 8 + 8 + 8);
+        assert_eq!(
+            u32::from_ne_bytes(message[0..4].try_into().unwrap()),
+            message.len() as u32
+        );
+        assert_eq!(&message[HEADER_LEN..HEADER_LEN + 2], &[2, 16]);
+        assert_eq!(&message[HEADER_LEN + 12..HEADER_LEN + 16], &[10, 0, 0, 1]);
+
+        let message = add_address(3, "fd42::1".parse().unwrap(), 64);
+        assert_eq!(message.len(), HEADER_LEN + 8 + 20 + 20);
+    }
+
+    #[test]
+    fn test_acknowledgement() {
+        let mut reply = message(libc::NLMSG_ERROR as u16, 0, &[]);
+        reply.extend((-libc::EEXIST).to_ne_bytes());
+        assert_eq!(acknowledgement(&reply), Some(-libc::EEXIST));
+
+        let reply = message(libc::RTM_NEWADDR, 0, &[]);
+        assert_eq!(acknowledgement(&reply), None);
+    }
+}
@@ -0,0 +1,485 @@
+//! Application protocols spoken by client flows, and the matching responders
+//! of the server. The server doesn't know what a client is going to speak,
+//! so the protocol of a connection is detected from its first bytes.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use rand::{Rng, distributions::WeightedIndex, seq::SliceRandom, thread_rng};
+use rand_distr::Distribution;
+
+use crate::{ApplicationMix, ValueDistribution, WorkerError};
+
+/// Requests with a header or a line longer than that are dropped.
+const MAX_HEADER: usize = 64 * 1024;
+
+/// Frames longer than that are dropped together with the rest of the input.
+const MAX_FRAME: usize = 16 * 1024 * 1024;
+
+/// Size of the frame prefix, a flag byte and a 32 bit length.
+const FRAME_PREFIX: usize = 5;
+
+/// Default frame payload size, if payload_size is not specified.
+const FRAME_PAYLOAD: usize = 32;
+
+const HTTP_METHODS: [&str; 2] = ["GET", "POST"];
+
+const HTTP_RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nServer: berserker\r\nContent-Type: text/plain\r\nContent-Length: 6\r\n\r\nhello\n";
+
+const WORDS: [&str; 12] = [
+    "api", "v1", "users", "orders", "items", "search", "static", "images",
+    "health", "metrics", "login", "cart",
+];
+
+const DOMAINS: [&str; 4] =
+    ["example.com", "example.net", "example.org", "test"];
+
+/// DNS record types.
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(super) enum Application {
+    Hello,
+    Http,
+    Dns,
+    Frames,
+}
+
+impl Application {
+    fn available(self, datagram: bool) -> bool {
+        match self {
+            Application::Hello | Application::Frames => true,
+            Application::Http => !datagram,
+            Application::Dns => datagram,
+        }
+    }
+
+    /// Next request to send via a flow, the payload has to fit into the
+    /// socket buffer.
+    pub fn request(
+        self,
+        index: usize,
+        payload_size: Option<ValueDistribution>,
+        buffer_size: usize,
+    ) -> Vec<u8> {
+        let size = payload_size
+            .map(|size| (size.sample() as usize).min(buffer_size).max(1));
+
+        match self {
+            Application::Hello => hello(index, size),
+            Application::Http => http_request(index, size, buffer_size),
+            Application::Dns => dns_query(),
+            Application::Frames => frame(
+                size.unwrap_or(FRAME_PAYLOAD)
+                    .min(buffer_size.saturating_sub(FRAME_PREFIX)),
+            ),
+        }
+    }
+}
+
+/// Picks an application protocol for a new flow according to the mix.
+#[derive(Debug)]
+pub(super) struct Applications {
+    streams: Option<WeightedIndex<f64>>,
+    datagrams: Option<WeightedIndex<f64>>,
+}
+
+const APPLICATIONS: [Application; 4] = [
+    Application::Hello,
+    Application::Http,
+    Application::Dns,
+    Application::Frames,
+];
+
+impl Applications {
+    pub fn new(mix: &ApplicationMix) -> Result<Self, WorkerError> {
+        let weights = [mix.hello, mix.http, mix.dns, mix.frames];
+        if weights.iter().any(|w| *w < 0.0) || weights.iter().all(|w| *w == 0.0)
+        {
+            return Err(WorkerError::InternalWithMessage(String::from(
+                "invalid application mix",
+            )));
+        }
+
+        // Weights of protocols not available for the transport are zeroed,
+        // if nothing is left hello is used
+        let index = |datagram: bool| {
+            let weights = APPLICATIONS
+                .iter()
+                .zip(weights)
+                .map(|(app, w)| if app.available(datagram) { w } else { 0.0 });
+            WeightedIndex::new(weights).ok()
+        };
+
+        Ok(Applications {
+            streams: index(false),
+            datagrams: index(true),
+        })
+    }
+
+    pub fn pick(&self, datagram: bool) -> Application {
+        let index = if datagram {
+            &self.datagrams
+        } else {
+            &self.streams
+        };
+
+        index.as_ref().map_or(Application::Hello, |index| {
+            APPLICATIONS[index.sample(&mut thread_rng())]
+        })
+    }
+}
+
+/// A short "hello" line by default, or a line of the specified size.
+fn hello(index: usize, size: Option<usize>) -> Vec<u8> {
+    let Some(size) = size else {
+        return format!("hello {}\n", index).into_bytes();
+    };
+
+    let mut message = vec![b'x'; size];
+    message[size - 1] = b'\n';
+    message
+}
+
+/// GET request with a random path and headers, or POST with a body of the
+/// specified size. The body is cut to fit the whole request into the buffer.
+fn http_request(
+    index: usize,
+    body: Option<usize>,
+    buffer_size: usize,
+) -> Vec<u8> {
+    let mut rng = thread_rng();
+
+    let depth = rng.gen_range(1..=3);
+    let path: String = (0..depth)
+        .map(|_| format!("/{}", WORDS.choose(&mut rng).unwrap()))
+        .collect();
+
+    let mut request = format!(
+        "{} {path}/{} HTTP/1.1\r\nHost: {}\r\nUser-Agent: berserker\r\nAccept: */*\r\nX-Request-Id: {index}\r\n",
+        HTTP_METHODS[body.is_some() as usize],
+        rng.gen_range(1..10000),
+        DOMAINS.choose(&mut rng).unwrap(),
+    );
+
+    if rng.gen_bool(0.5) {
+        request.push_str("Accept-Language: en-US,en;q=0.5\r\n");
+    }
+    if rng.gen_bool(0.5) {
+        request.push_str("Cache-Control: no-cache\r\n");
+    }
+    if rng.gen_bool(0.5) {
+        request.push_str(&format!(
+            "Cookie: session={:016x}\r\n",
+            rng.r#gen::<u64>()
+        ));
+    }
+
+    match body {
+        Some(size) => {
+            let headers = |size| {
+                format!(
+                    "Content-Type: application/octet-stream\r\nContent-Length: {size}\r\n\r\n"
+                )
+            };
+
+            // Cutting the body never makes the headers longer
+            let size = size.min(
+                buffer_size.saturating_sub(request.len() + headers(size).len()),
+            );
+            request.push_str(&headers(size));
+            let mut request = request.into_bytes();
+            request.resize(request.len() + size, b'x');
+            request
+        }
+        None => {
+            request.push_str("\r\n");
+            request.into_bytes()
+        }
+    }
+}
+
+/// DNS query for A or AAAA record of a random name.
+fn dns_query() -> Vec<u8> {
+    let mut rng = thread_rng();
+
+    let mut query = Vec::with_capacity(64);
+    query.extend(rng.r#gen::<u16>().to_be_bytes()); // id
+    query.extend(0x0100u16.to_be_bytes()); // recursion desired
+    query.extend(1u16.to_be_bytes()); // questions
+    query.extend([0; 6]); // answers, authority and additional records
+
+    let label: String = (0..rng.gen_range(3..=12))
+        .map(|_| rng.gen_range(b'a'..=b'z') as char)
+        .collect();
+    let domain = DOMAINS.choose(&mut rng).unwrap();
+    for part in [label.as_str()].into_iter().chain(domain.split('.')) {
+        query.push(part.len() as u8);
+        query.extend(part.as_bytes());
+    }
+    query.push(0);
+
+    let kind = if rng.gen_bool(0.5) { TYPE_A } else { TYPE_AAAA };
+    query.extend(kind.to_be_bytes());
+    query.extend(CLASS_IN.to_be_bytes());
+    query
+}
+
+/// Uncompressed frame with a random payload of the specified size.
+fn frame(size: usize) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_PREFIX + size);
+    frame.push(0);
+    frame.extend((size as u32).to_be_bytes());
+    frame.extend((0..size).map(|_| thread_rng().r#gen::<u8>()));
+    frame
+}
+
+/// Protocol of a connection from its first bytes, if there is enough of
+/// them.
+pub(super) fn detect(input: &[u8]) -> Option<Application> {
+    let first = *input.first()?;
+    if first == 0 {
+        return Some(Application::Frames);
+    }
+
+    for method in HTTP_METHODS {
+        let prefix = format!("{method} ");
+        if input.starts_with(prefix.as_bytes()) {
+            return Some(Application::Http);
+        }
+
+        // Could be the beginning of a request
+        if prefix.as_bytes().starts_with(input) {
+            return None;
+        }
+    }
+
+    Some(Application::Hello)
+}
+
+/// Answer every complete request in the input, removing it from there.
+/// Returns the number of requests.
+pub(super) fn respond(
+    application: Application,
+    input: &mut Vec<u8>,
+    output: &mut Vec<u8>,
+) -> u64 {
+    let mut requests = 0;
+
+    loop {
+        let consumed = match application {
+            Application::Hello | Application::Dns => {
+                let Some(end) = input.iter().position(|b| *b == b'\n') else {
+                    break;
+                };
+
+                output.extend_from_slice(b"hello\n");
+                end + 1
+            }
+            Application::Http => {
+                let Some(end) = input.windows(4).position(|w| w == b"\r\n\r\n")
+                else {
+                    break;
+                };
+
+                let header = String::from_utf8_lossy(&input[..end]);
+                let length = header
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| {
+                        name.trim().eq_ignore_ascii_case("content-length")
+                    })
+                    .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                    .unwrap_or(0);
+
+                if input.len() < end + 4 + length {
+                    break;
+                }
+
+                output.extend_from_slice(HTTP_RESPONSE);
+                end + 4 + length
+            }
+            Application::Frames => {
+                let Some(prefix) = input.get(1..FRAME_PREFIX) else {
+                    break;
+                };
+
+                let length = u32::from_be_bytes(prefix.try_into().unwrap());
+                if length as usize > MAX_FRAME {
+                    input.clear();
+                    break;
+                }
+
+                if input.len() < FRAME_PREFIX + length as usize {
+                    break;
+                }
+
+                output.push(0);
+                output.extend(6u32.to_be_bytes());
+                output.extend_from_slice(b"hello\n");
+                FRAME_PREFIX + length as usize
+            }
+        };
+
+        input.drain(..consumed);
+        requests += 1;
+    }
+
+    if application != Application::Frames && input.len() > MAX_HEADER {
+        input.clear();
+    }
+
+    requests
+}
+
+/// Answer to a DNS query with a made up address from the documentation
+/// range, or None if the datagram is not a query.
+pub(super) fn dns_response(query: &[u8]) -> Option<Vec<u8>> {
+    let header = query.get(..12)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+
+    // Only standard queries with a single question
+    if flags & 0xf800 != 0 || questions != 1 {
+        return None;
+    }
+
+    let mut end = 12;
+    loop {
+        let len = *query.get(end)? as usize;
+        end += 1;
+        if len == 0 {
+            break;
+        }
+        if len > 63 {
+            return None;
+        }
+        end += len;
+    }
+
+    let question = query.get(12..end + 4)?;
+    let kind = u16::from_be_bytes([query[end], query[end + 1]]);
+    let class = u16::from_be_bytes([query[end + 2], query[end + 3]]);
+
+    let data = match (kind, class) {
+        (TYPE_A, CLASS_IN) => {
+            Some(Ipv4Addr::new(192, 0, 2, 1).octets().to_vec())
+        }
+        (TYPE_AAAA, CLASS_IN) => Some(
+            Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1)
+                .octets()
+                .to_vec(),
+        ),
+        _ => None,
+    };
+
+    let mut response = Vec::with_capacity(query.len() + 32);
+    response.extend_from_slice(&header[..2]); // id
+    // response, recursion available, recursion desired copied
+    response.extend((0x8080u16 | (flags & 0x0100)).to_be_bytes());
+    response.extend(1u16.to_be_bytes());
+    response.extend((data.is_some() as u16).to_be_bytes());
+    response.extend([0; 4]);
+    response.extend_from_slice(question);
+
+    if let Some(data) = data {
+        response.extend(0xc00cu16.to_be_bytes()); // name of the question
+        response.extend(kind.to_be_bytes());
+        response.extend(CLASS_IN.to_be_bytes());
+        response.extend(60u32.to_be_bytes()); // ttl
+        response.extend((data.len() as u16).to_be_bytes());
+        response.extend(data);
+    }
+
+    Some(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_respond() {
+        let cases = [
+            (Application::Hello, hello(1, None)),
+            (Application::Http, http_request(1, None, 4096)),
+            (Application::Http, http_request(1, Some(100), 4096)),
+            (Application::Frames, frame(100)),
+        ];
+
+        for (application, request) in cases {
+            assert_eq!(detect(&request), Some(application));
+
+            // Two requests, the second one arrives in two parts
+            let (head, tail) = request.split_at(request.len() / 2);
+            let mut input = [request.as_slice(), head].concat();
+            let mut output = vec![];
+
+            assert_eq!(respond(application, &mut input, &mut output), 1);
+            assert_eq!(input, head);
+
+            input.extend_from_slice(tail);
+            assert_eq!(respond(application, &mut input, &mut output), 1);
+            assert!(input.is_empty());
+            assert!(!output.is_empty());
+        }
+
+        assert_eq!(detect(b"PO"), None);
+        assert_eq!(detect(b"hel"), Some(Application::Hello));
+    }
+
+    #[test]
+    fn test_request_size() {
+        let payload_size = Some(ValueDistribution::Constant { value: 1024 });
+
+        for application in APPLICATIONS {
+            let request = application.request(1, payload_size, 1024);
+            assert!(request.len() <= 1024, "{application:?} doesn't fit");
+        }
+
+        let request = Application::Http.request(1, payload_size, 1024);
+        assert_eq!(detect(&request), Some(Application::Http));
+        let mut input = request.clone();
+        assert_eq!(respond(Application::Http, &mut input, &mut vec![]), 1);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_dns_response() {
+        for _ in 0..10 {
+            let query = dns_query();
+            let response = dns_response(&query).unwrap();
+
+            assert_eq!(response[..2], query[..2]);
+            // response flag and a single answer
+            assert_eq!(response[2] & 0x80, 0x80);
+            assert_eq!(response[6..8], [0, 1]);
+            assert_eq!(response[12..query.len()], query[12..]);
+
+            // responses are not answered
+            assert_eq!(dns_response(&response), None);
+        }
+
+        assert_eq!(dns_response(&hello(1, None)), None);
+        assert_eq!(dns_response(&frame(10)), None);
+    }
+
+    #[test]
+    fn test_applications() {
+        let mix = ApplicationMix {
+            hello: 0.0,
+            http: 1.0,
+            dns: 0.0,
+            frames: 0.0,
+        };
+
+        let applications = Applications::new(&mix).unwrap();
+        assert_eq!(applications.pick(false), Application::Http);
+        assert_eq!(applications.pick(true), Application::Hello);
+
+        assert!(
+            Applications::new(&ApplicationMix { http: 0.0, ..mix }).is_err()
+        );
+    }
+}
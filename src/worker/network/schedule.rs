@@ -0,0 +1,184 @@
+//! When client flows of the network workload are allowed to send data.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use rand::{Rng, thread_rng};
+use rand_distr::Exp;
+use smoltcp::iface::SocketHandle;
+
+#[derive(Debug)]
+pub(super) enum Schedule {
+    /// Use global timer to throttle sending data via connections, since the
+    /// main purpose is to excercise connection monitoring. Sending data too
+    /// frequently we risk producing too much load and making connetion
+    /// monitoring less reliable. Only one flow sends per interval, in
+    /// milliseconds.
+    Global { timer: SystemTime, interval: u64 },
+
+    /// Every flow sends independently, following Poisson arrival with the
+    /// rate per second.
+    PerFlow {
+        rate: Exp<f64>,
+        next: HashMap<SocketHandle, SystemTime>,
+    },
+
+    /// Every flow sends as much as possible.
+    Bulk,
+}
+
+impl Schedule {
+    pub fn new(send_interval: u64, send_rate: Option<f64>, bulk: bool) -> Self {
+        if bulk {
+            Schedule::Bulk
+        } else if let Some(rate) = send_rate {
+            Schedule::PerFlow {
+                rate: Exp::new(rate).unwrap(),
+                next: HashMap::new(),
+            }
+        } else {
+            Schedule::Global {
+                timer: SystemTime::now(),
+                interval: send_interval,
+            }
+        }
+    }
+
+    /// Whether the flow could send now. If so, the next sending time is
+    /// scheduled.
+    pub fn due(&mut self, handle: SocketHandle) -> bool {
+        self.due_at(handle, SystemTime::now(), &mut thread_rng())
+    }
+
+    fn due_at(
+        &mut self,
+        handle: SocketHandle,
+        now: SystemTime,
+        rng: &mut impl Rng,
+    ) -> bool {
+        match self {
+            Schedule::Global { timer, interval } => {
+                let elapsed =
+                    now.duration_since(*timer).unwrap_or_default().as_millis()
+                        as u64;
+                if elapsed > *interval {
+                    *timer = now;
+                    true
+                } else {
+                    false
+                }
+            }
+            Schedule::PerFlow { rate, next } => {
+                // A new flow sends right away
+                let due = next.get(&handle).is_none_or(|next| *next <= now);
+
+                if due {
+                    let interval: f64 = rng.sample(*rate);
+                    next.insert(
+                        handle,
+                        now + Duration::from_secs_f64(interval),
+                    );
+                }
+
+                due
+            }
+            Schedule::Bulk => true,
+        }
+    }
+
+    /// How long until the next flow is due, so that the client doesn't
+    /// oversleep it. Flows overdue already are waiting for something else,
+    /// e.g. for room in the socket buffer, and are not taken into account.
+    pub fn poll_delay(&self) -> Option<Duration> {
+        self.poll_delay_at(SystemTime::now())
+    }
+
+    fn poll_delay_at(&self, now: SystemTime) -> Option<Duration> {
+        match self {
+            Schedule::Global { timer, interval } => {
+                let due = *timer + Duration::from_millis(*interval + 1);
+                due.duration_since(now).ok()
+            }
+            Schedule::PerFlow { next, .. } => next
+                .values()
+                .filter_map(|next| next.duration_since(now).ok())
+                .min(),
+            Schedule::Bulk => Some(Duration::ZERO),
+        }
+    }
+
+    /// Forget about a closed flow.
+    pub fn remove(&mut self, handle: SocketHandle) {
+        if let Schedule::PerFlow { next, .. } = self {
+            next.remove(&handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rand::{SeedableRng, rngs::StdRng};
+    use smoltcp::{
+        iface::SocketSet,
+        socket::udp::{PacketBuffer, Socket},
+    };
+
+    #[test]
+    fn test_per_flow() {
+        let mut sockets = SocketSet::new(vec![]);
+        let mut handle = || {
+            let buffer = || PacketBuffer::new(vec![], vec![]);
+            sockets.add(Socket::new(buffer(), buffer()))
+        };
+        let (first, second) = (handle(), handle());
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut now = SystemTime::UNIX_EPOCH;
+
+        let mut schedule = Schedule::new(0, Some(1000.0), false);
+        assert_eq!(schedule.poll_delay_at(now), None);
+
+        // New flows are due right away, then wait for the next time
+        assert!(schedule.due_at(first, now, &mut rng));
+        assert!(schedule.due_at(second, now, &mut rng));
+        assert!(!schedule.due_at(first, now, &mut rng));
+        let delay = schedule.poll_delay_at(now).unwrap();
+        assert!(delay < Duration::from_secs(1));
+
+        // On average a thousand sends per second, not limited by anything
+        // else but the rate
+        let end = now + Duration::from_secs(10);
+        let mut sent = 0;
+        while now < end {
+            now += schedule.poll_delay_at(now).unwrap_or_default();
+            sent += schedule.due_at(first, now, &mut rng) as u64;
+            sent += schedule.due_at(second, now, &mut rng) as u64;
+        }
+        assert!((19_000..21_000).contains(&sent), "sent {sent}");
+
+        schedule.remove(first);
+        schedule.remove(second);
+        assert_eq!(schedule.poll_delay_at(now), None);
+    }
+
+    #[test]
+    fn test_global() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut schedule = Schedule::new(50, None, false);
+        let start = SystemTime::now();
+
+        let handle = SocketHandle::default();
+        assert!(!schedule.due_at(handle, start, &mut rng));
+        let delay = schedule.poll_delay_at(start).unwrap();
+        assert!(delay <= Duration::from_millis(51));
+
+        // Only one flow sends per interval
+        let now = start + delay;
+        assert!(schedule.due_at(handle, now, &mut rng));
+        assert!(!schedule.due_at(handle, now, &mut rng));
+    }
+}
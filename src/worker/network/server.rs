@@ -0,0 +1,472 @@
+//! Server side of the network workload. All connections and datagrams are
+//! handled by a single thread via epoll, so that the number of connections
+//! is limited only by file descriptors and memory, not by threads.
+
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, trace};
+use nix::{
+    errno::Errno,
+    sys::epoll::{
+        EpollCreateFlags, EpollEvent, EpollFlags, EpollOp, epoll_create1,
+        epoll_ctl, epoll_wait,
+    },
+};
+
+use crate::BaseConfig;
+
+use super::protocols::{self, Application};
+
+/// Event data for the listening socket, connections use their fd.
+const LISTENER: u64 = u64::MAX;
+
+/// Event data for the UDP socket.
+const DATAGRAMS: u64 = u64::MAX - 1;
+
+/// Stop reading from a connection while it has more unsent data than that.
+const MAX_OUTPUT: usize = 1024 * 1024;
+
+/// How often to report server stats.
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Per-connection stats, reported when the connection is closed.
+#[derive(Debug)]
+struct ConnectionStats {
+    opened: Instant,
+    requests: u64,
+    received: u64,
+    sent: u64,
+}
+
+#[derive(Debug)]
+struct Connection {
+    stream: TcpStream,
+    peer: SocketAddr,
+
+    /// Protocol the peer speaks, detected from the first bytes.
+    application: Option<Application>,
+
+    /// Received part of the current request.
+    input: Vec<u8>,
+
+    /// Responses which didn't fit into the socket buffer.
+    output: Vec<u8>,
+
+    /// The peer has shut down its side, close once the output is sent.
+    eof: bool,
+
+    stats: ConnectionStats,
+}
+
+/// Aggregated stats over all connections.
+#[derive(Debug, Default)]
+struct ServerStats {
+    accepted: u64,
+    closed: u64,
+    requests: u64,
+    datagrams: u64,
+    received: u64,
+    sent: u64,
+}
+
+pub(super) struct Server {
+    epoll: OwnedFd,
+    listener: Option<TcpListener>,
+    udp: Option<UdpSocket>,
+    connections: HashMap<RawFd, Connection>,
+    stats: ServerStats,
+
+    /// Send all received data back instead of a reply per request.
+    bidirectional: bool,
+}
+
+impl Server {
+    /// Create a server for TCP connections, UDP datagrams or both. DNS
+    /// queries are answered and other datagrams are echoed back to the
+    /// sender, as well as everything received over TCP if the server is
+    /// bidirectional.
+    pub fn new(
+        listener: Option<TcpListener>,
+        udp: Option<UdpSocket>,
+        bidirectional: bool,
+    ) -> io::Result<Self> {
+        let epoll = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)?;
+        let server = Server {
+            epoll: unsafe { OwnedFd::from_raw_fd(epoll) },
+            listener,
+            udp,
+            connections: HashMap::new(),
+            stats: ServerStats::default(),
+            bidirectional,
+        };
+
+        if let Some(listener) = &server.listener {
+            listener.set_nonblocking(true)?;
+            server.control(
+                EpollOp::EpollCtlAdd,
+                listener.as_raw_fd(),
+                LISTENER,
+                false,
+            )?;
+        }
+
+        if let Some(udp) = &server.udp {
+            udp.set_nonblocking(true)?;
+            server.control(
+                EpollOp::EpollCtlAdd,
+                udp.as_raw_fd(),
+                DATAGRAMS,
+                false,
+            )?;
+        }
+
+        Ok(server)
+    }
+
+    fn control(
+        &self,
+        op: EpollOp,
+        fd: RawFd,
+        data: u64,
+        writable: bool,
+    ) -> io::Result<()> {
+        self.watch(op, fd, data, true, writable)
+    }
+
+    fn watch(
+        &self,
+        op: EpollOp,
+        fd: RawFd,
+        data: u64,
+        readable: bool,
+        writable: bool,
+    ) -> io::Result<()> {
+        let mut flags = EpollFlags::empty();
+        if readable {
+            flags |= EpollFlags::EPOLLIN;
+        }
+        if writable {
+            flags |= EpollFlags::EPOLLOUT;
+        }
+
+        let mut event = EpollEvent::new(flags, data);
+        epoll_ctl(self.epoll.as_raw_fd(), op, fd, &mut event)?;
+        Ok(())
+    }
+
+    /// Serve connections forever, every request gets a short response in
+    /// the protocol of the connection, and every datagram is answered.
+    pub fn run(&mut self, config: &BaseConfig) -> io::Result<()> {
+        let mut events = vec![EpollEvent::empty(); 1024];
+        let mut report = Instant::now();
+
+        loop {
+            if report.elapsed() > REPORT_INTERVAL {
+                info!(
+                    "{}-{}: Connections {}, accepted {}, closed {}, requests {}, datagrams {}, received {}, sent {}",
+                    config.cpu.id,
+                    config.process,
+                    self.connections.len(),
+                    self.stats.accepted,
+                    self.stats.closed,
+                    self.stats.requests,
+                    self.stats.datagrams,
+                    self.stats.received,
+                    self.stats.sent
+                );
+                report = Instant::now();
+            }
+
+            self.wait(&mut events, REPORT_INTERVAL)?;
+        }
+    }
+
+    /// Wait for events not longer than the timeout, and handle them.
+    fn wait(
+        &mut self,
+        events: &mut [EpollEvent],
+        timeout: Duration,
+    ) -> io::Result<()> {
+        let timeout = timeout.as_millis() as isize;
+        let n = match epoll_wait(self.epoll.as_raw_fd(), events, timeout) {
+            Ok(n) => n,
+            Err(Errno::EINTR) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        for event in &events[..n] {
+            if event.data() == LISTENER {
+                self.accept();
+            } else if event.data() == DATAGRAMS {
+                self.echo();
+            } else {
+                self.serve(event.data() as RawFd, event.events());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn accept(&mut self) {
+        let Some(listener) = &self.listener else {
+            return;
+        };
+
+        loop {
+            let (stream, peer) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    // E.g. out of file descriptors, try again on the next
+                    // event
+                    debug!("Failed to accept, {}", e);
+                    return;
+                }
+            };
+
+            if let Err(e) = stream.set_nonblocking(true) {
+                debug!("Failed to set {} non-blocking, {}", peer, e);
+                continue;
+            }
+
+            let fd = stream.as_raw_fd();
+            if let Err(e) =
+                self.control(EpollOp::EpollCtlAdd, fd, fd as u64, false)
+            {
+                debug!("Failed to watch {}, {}", peer, e);
+                continue;
+            }
+
+            trace!("Accepted {}", peer);
+            self.stats.accepted += 1;
+            self.connections.insert(
+                fd,
+                Connection {
+                    stream,
+                    peer,
+                    application: None,
+                    input: vec![],
+                    output: vec![],
+                    eof: false,
+                    stats: ConnectionStats {
+                        opened: Instant::now(),
+                        requests: 0,
+                        received: 0,
+                        sent: 0,
+                    },
+                },
+            );
+        }
+    }
+
+    /// Read all available data, answer every complete request and send as
+    /// much as possible. The connection is closed on an error, or on EOF
+    /// once everything pending is sent.
+    fn serve(&mut self, fd: RawFd, events: EpollFlags) {
+        let Some(conn) = self.connections.get_mut(&fd) else {
+            return;
+        };
+
+        let mut open = true;
+        let mut buf = [0u8; 4096];
+
+        // Errors and hangups are discovered while reading
+        if !conn.eof
+            && events.intersects(
+                EpollFlags::EPOLLIN
+                    | EpollFlags::EPOLLHUP
+                    | EpollFlags::EPOLLERR,
+            )
+        {
+            loop {
+                match conn.stream.read(&mut buf) {
+                    Ok(0) => {
+                        conn.eof = true;
+                        break;
+                    }
+                    Ok(n) => {
+                        conn.stats.received += n as u64;
+                        self.stats.received += n as u64;
+                        conn.input.extend_from_slice(&buf[..n]);
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        trace!("Failed to read from {}, {}", conn.peer, e);
+                        open = false;
+                        break;
+                    }
+                }
+            }
+
+            if self.bidirectional {
+                let lines = conn.input.iter().filter(|b| **b == b'\n').count();
+                conn.output.append(&mut conn.input);
+                conn.stats.requests += lines as u64;
+                self.stats.requests += lines as u64;
+            } else {
+                if conn.application.is_none() {
+                    conn.application = protocols::detect(&conn.input);
+                    trace!("{} speaks {:?}", conn.peer, conn.application);
+                }
+
+                if let Some(application) = conn.application {
+                    let requests = protocols::respond(
+                        application,
+                        &mut conn.input,
+                        &mut conn.output,
+                    );
+                    conn.stats.requests += requests;
+                    self.stats.requests += requests;
+                }
+            }
+        }
+
+        while open && !conn.output.is_empty() {
+            match conn.stream.write(&conn.output) {
+                Ok(n) => {
+                    conn.output.drain(..n);
+                    conn.stats.sent += n as u64;
+                    self.stats.sent += n as u64;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    trace!("Failed to send to {}, {}", conn.peer, e);
+                    open = false;
+                }
+            }
+        }
+
+        let pending = !conn.output.is_empty();
+        if !open || (conn.eof && !pending) {
+            self.close(fd);
+            return;
+        }
+
+        // Do not read more while the peer doesn't keep up with the output
+        let readable = !conn.eof && conn.output.len() < MAX_OUTPUT;
+        if let Err(e) =
+            self.watch(EpollOp::EpollCtlMod, fd, fd as u64, readable, pending)
+        {
+            debug!("Failed to watch connection {}, {}", fd, e);
+            self.close(fd);
+        }
+    }
+
+    /// Answer DNS queries and send every other available datagram back. Best
+    /// effort, if there is no room in the socket buffer the reply is
+    /// dropped.
+    fn echo(&mut self) {
+        let Some(udp) = &self.udp else {
+            return;
+        };
+
+        let mut buf = [0u8; 65536];
+
+        loop {
+            let (n, peer) = match udp.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    trace!("Failed to receive datagram, {}", e);
+                    return;
+                }
+            };
+
+            self.stats.datagrams += 1;
+            self.stats.received += n as u64;
+
+            let response = protocols::dns_response(&buf[..n]);
+            let reply = response.as_deref().unwrap_or(&buf[..n]);
+
+            match udp.send_to(reply, peer) {
+                Ok(n) => self.stats.sent += n as u64,
+                Err(e) => trace!("Failed to echo to {}, {}", peer, e),
+            }
+        }
+    }
+
+    fn close(&mut self, fd: RawFd) {
+        if let Some(conn) = self.connections.remove(&fd) {
+            self.stats.closed += 1;
+            debug!(
+                "Closed {} after {:?}, requests {}, received {}, sent {}",
+                conn.peer,
+                conn.stats.opened.elapsed(),
+                conn.stats.requests,
+                conn.stats.received,
+                conn.stats.sent
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{net::Shutdown, thread};
+
+    #[test]
+    fn test_half_close() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut server = Server::new(Some(listener), None, true).unwrap();
+
+        // Everything sent before shutting down the write side has to come
+        // back, even if the EOF is read at the same time as the data
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"hello\n").unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            thread::sleep(Duration::from_millis(100));
+
+            let mut echoed = vec![];
+            stream.read_to_end(&mut echoed).unwrap();
+            echoed
+        });
+
+        let mut events = vec![EpollEvent::empty(); 16];
+        let deadline = Instant::now() + Duration::from_millis(500);
+        while Instant::now() < deadline {
+            server.wait(&mut events, Duration::from_millis(10)).unwrap();
+        }
+
+        assert_eq!(client.join().unwrap(), b"hello\n");
+        assert_eq!(server.stats.closed, 1);
+        assert!(server.connections.is_empty());
+    }
+
+    #[test]
+    fn test_datagram() {
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let mut server = Server::new(None, Some(udp), false).unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        client.send_to(b"hello\n", addr).unwrap();
+
+        let mut events = vec![EpollEvent::empty(); 16];
+        server
+            .wait(&mut events, Duration::from_millis(500))
+            .unwrap();
+
+        let mut buf = [0u8; 64];
+        let (n, peer) = client.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello\n");
+        assert_eq!(peer, addr);
+        assert_eq!(server.stats.datagrams, 1);
+    }
+}
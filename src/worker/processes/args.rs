@@ -0,0 +1,194 @@
+use std::net::Ipv4Addr;
+
+use rand::{Rng, distributions::Alphanumeric, seq::SliceRandom, thread_rng};
+
+use crate::{ValueDistribution, Workload, stub::StubOptions};
+
+/// Command line for a spawned process.
+#[derive(Debug, Clone, Default)]
+pub(super) struct CommandLine {
+    /// argv[0], if not specified the executable name is used.
+    pub program: Option<String>,
+
+    pub args: Vec<String>,
+
+    /// Additional environment variables in the form "KEY=value".
+    pub env: Vec<String>,
+}
+
+/// Generates command lines for spawned processes according to the workload
+/// configuration, either from templates or completely random.
+#[derive(Debug, Clone)]
+pub(super) struct Generator {
+    args_count: ValueDistribution,
+    args_length: ValueDistribution,
+    env_count: ValueDistribution,
+    env_length: ValueDistribution,
+    templates: Vec<String>,
+
+    /// Options for the built-in stub, if it's used as the exec target.
+    stub: Option<StubOptions>,
+}
+
+impl Generator {
+    pub fn new(workload: &Workload) -> Self {
+        let Workload::Processes {
+            args_count,
+            args_length,
+            env_count,
+            env_length,
+            templates,
+            builtin_stub,
+            stub_open_files,
+            stub_connect,
+            ..
+        } = workload
+        else {
+            unreachable!()
+        };
+
+        let stub = builtin_stub.then(|| StubOptions {
+            lifetime_ms: 0,
+            open_files: *stub_open_files,
+            connect: stub_connect.clone(),
+            tree: vec![],
+        });
+
+        Generator {
+            args_count: *args_count,
+            args_length: *args_length,
+            env_count: *env_count,
+            env_length: *env_length,
+            templates: templates.clone(),
+            stub,
+        }
+    }
+
+    /// Generate a command line for a process with the specified lifetime and
+    /// the process tree rooted at it, see `StubOptions::tree`. Both matter
+    /// only for the built-in stub, which options are going first, followed by
+    /// the generated arguments. Only the built-in stub is able to spawn a
+    /// tree, for anything else there is no command line if the tree has more
+    /// than one process.
+    pub fn generate(&self, lifetime: u64, tree: &[u64]) -> Option<CommandLine> {
+        if tree.len() > 1 && self.stub.is_none() {
+            return None;
+        }
+
+        let mut cmdline = self.generate_random();
+
+        if let Some(stub) = &self.stub {
+            let options = StubOptions {
+                lifetime_ms: lifetime,
+                tree: tree.to_vec(),
+                ..stub.clone()
+            };
+            let mut args = options.to_args();
+            args.append(&mut cmdline.args);
+            cmdline.args = args;
+        }
+
+        Some(cmdline)
+    }
+
+    fn generate_random(&self) -> CommandLine {
+        let env = (0..self.env_count.sample())
+            .map(|i| {
+                let value = random_string(self.env_length.sample() as usize);
+                format!("BERSERKER_{i}={value}")
+            })
+            .collect();
+
+        if let Some(template) = self.templates.choose(&mut thread_rng()) {
+            let rendered = render(template);
+            let mut words = rendered.split_whitespace().map(String::from);
+
+            return CommandLine {
+                program: words.next(),
+                args: words.collect(),
+                env,
+            };
+        }
+
+        let args = (0..self.args_count.sample())
+            .map(|_| random_string(self.args_length.sample() as usize))
+            .collect();
+
+        CommandLine {
+            program: None,
+            args,
+            env,
+        }
+    }
+}
+
+/// Substitute every placeholder in the template with a new random value.
+/// Unknown placeholders are left as is.
+pub(super) fn render(template: &str) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+
+        let placeholder = &rest[1..end];
+        match placeholder {
+            "random_ip" => result.push_str(
+                &Ipv4Addr::from(thread_rng().r#gen::<u32>()).to_string(),
+            ),
+            "random_port" => result
+                .push_str(&thread_rng().gen_range(1..=u16::MAX).to_string()),
+            "random_path" => {
+                let segments = thread_rng().gen_range(1..=4);
+                let path: Vec<String> = (0..segments)
+                    .map(|_| random_string(thread_rng().gen_range(3..=10)))
+                    .collect();
+                result.push_str(&path.join("/"));
+            }
+            "random_string" => result.push_str(&random_string(8)),
+            _ => result.push_str(&rest[..=end]),
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    result.push_str(rest);
+    result
+}
+
+pub(super) fn random_string(len: usize) -> String {
+    thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let rendered = render("curl -s http://{random_ip}:{random_port}/{x}");
+        let url = rendered.strip_prefix("curl -s http://").unwrap();
+        let (addr, rest) = url.split_once(':').unwrap();
+        let (port, path) = rest.split_once('/').unwrap();
+
+        assert!(addr.parse::<Ipv4Addr>().is_ok());
+        assert!(port.parse::<u16>().is_ok());
+        assert_eq!(path, "{x}");
+
+        let rendered = render("cat /{random_path} {random_string} {");
+        let words: Vec<_> = rendered.split_whitespace().collect();
+        assert_eq!(words.len(), 4);
+        assert!(words[1].starts_with('/'));
+        assert_eq!(words[2].len(), 8);
+        assert_eq!(words[3], "{");
+    }
+}
@@ -0,0 +1,423 @@
+use std::{
+    env,
+    ffi::{CString, c_char},
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::{OpenOptionsExt, symlink},
+    },
+    path::PathBuf,
+    process,
+};
+
+use log::debug;
+use nix::sys::memfd::{MemFdCreateFlag, memfd_create};
+use rand::{Rng, thread_rng};
+
+use crate::{CommLink, ExecMode, worker::find_in_path};
+
+use super::args::CommandLine;
+
+/// Minimal static x86_64 ELF executable, which immediately exits with 0. The
+/// same as stub.asm, but embedded to be able to produce the binary at
+/// runtime without any external dependencies.
+#[rustfmt::skip]
+pub(super) const STUB: &[u8] = &[
+    // ELF header: magic, 64 bit, little endian, version 1, SysV ABI, padding
+    0x7f, b'E', b'L', b'F', 0x02, 0x01, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // e_type EXEC, e_machine x86_64, e_version 1
+    0x02, 0x00, 0x3e, 0x00, 0x01, 0x00, 0x00, 0x00,
+    // e_entry 0x400078, right after the headers
+    0x78, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // e_phoff 64
+    0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // e_shoff 0
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // e_flags 0, e_ehsize 64, e_phentsize 56
+    0x00, 0x00, 0x00, 0x00, 0x40, 0x00, 0x38, 0x00,
+    // e_phnum 1, no sections
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // Program header: p_type LOAD, p_flags R+X
+    0x01, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00, 0x00,
+    // p_offset 0
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // p_vaddr 0x400000
+    0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // p_paddr 0x400000
+    0x00, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // p_filesz 130
+    0x82, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // p_memsz 130
+    0x82, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // p_align 0x1000
+    0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // xor rdi, rdi
+    0x48, 0x31, 0xff,
+    // mov eax, 60 (exit)
+    0xb8, 0x3c, 0x00, 0x00, 0x00,
+    // syscall
+    0x0f, 0x05,
+];
+
+/// Executable to produce payload from, either the minimal stub, or berserker
+/// itself.
+struct Image {
+    /// Where to find it on disk, to exec as is or to link. Needed only for
+    /// the path exec mode.
+    path: Option<PathBuf>,
+
+    /// Content to produce the executable from, not needed for the path exec
+    /// mode.
+    content: Vec<u8>,
+
+    /// Optional interpreter argument for scripts.
+    interpreter_arg: &'static str,
+}
+
+impl Image {
+    fn new(mode: ExecMode, builtin: bool) -> io::Result<Self> {
+        if !builtin {
+            return Ok(Image {
+                path: find_in_path("stub"),
+                content: STUB.to_vec(),
+                interpreter_arg: "",
+            });
+        }
+
+        let path = env::current_exe()?;
+        let content = match mode {
+            ExecMode::Path => vec![],
+            _ => fs::read(&path)?,
+        };
+
+        Ok(Image {
+            path: Some(path),
+            content,
+            interpreter_arg: " stub",
+        })
+    }
+}
+
+/// What to exec, either a path or a file descriptor for execveat.
+#[derive(Debug)]
+enum Target {
+    Path(CString),
+    Fd(OwnedFd),
+}
+
+/// An executable prepared once according to the exec mode, to be spawned
+/// many times afterwards. There could be multiple executables under
+/// different names, in this case one is picked up randomly every time.
+#[derive(Debug)]
+pub(super) struct Payload {
+    /// Executables with their names.
+    targets: Vec<(String, Target)>,
+
+    /// File descriptors to keep open, e.g. to exec via /proc/self/fd.
+    fds: Vec<OwnedFd>,
+
+    /// Scratch directory with all the files, to clean up at the end.
+    dir: PathBuf,
+}
+
+impl Payload {
+    /// Prepare executables for every name. If `builtin` is set, berserker
+    /// itself is used instead of the minimal stub, to be exec'd in the stub
+    /// mode.
+    pub fn new(
+        mode: ExecMode,
+        names: &[String],
+        link: CommLink,
+        builtin: bool,
+    ) -> io::Result<Self> {
+        let base = match mode {
+            ExecMode::Shm => PathBuf::from("/dev/shm"),
+            _ => env::temp_dir(),
+        };
+
+        let mut payload = Payload {
+            targets: vec![],
+            fds: vec![],
+            dir: base.join(format!("berserker-{}", process::id())),
+        };
+
+        // Names become file names in the scratch directory
+        if let Some(name) = names.iter().find(|name| {
+            matches!(name.as_str(), "" | "." | "..")
+                || name.contains(['/', '\0'])
+        }) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid comm name {name:?}"),
+            ));
+        }
+
+        fs::create_dir_all(&payload.dir)?;
+        let image = Image::new(mode, builtin)?;
+
+        if names.is_empty() {
+            let target = payload.prepare(mode, link, &image, "stub", true)?;
+            payload.targets.push((String::from("stub"), target));
+        }
+
+        for name in names {
+            let target = payload.prepare(mode, link, &image, name, false)?;
+            payload.targets.push((name.clone(), target));
+        }
+
+        debug!("Prepared payload {:?}", payload);
+        Ok(payload)
+    }
+
+    /// Prepare a single executable with the specified name. The image path
+    /// is used as is only if no specific name was requested.
+    fn prepare(
+        &mut self,
+        mode: ExecMode,
+        link: CommLink,
+        image: &Image,
+        name: &str,
+        as_is: bool,
+    ) -> io::Result<Target> {
+        let path = self.dir.join(name);
+
+        let target = match mode {
+            ExecMode::Path => {
+                let source = image
+                    .path
+                    .clone()
+                    .ok_or(io::Error::from_raw_os_error(libc::ENOENT))?;
+
+                if as_is {
+                    return Ok(Target::Path(path_to_cstring(source)));
+                }
+
+                match link {
+                    CommLink::Symlink => symlink(source, &path)?,
+                    CommLink::Copy => {
+                        fs::copy(source, &path)?;
+                    }
+                }
+
+                Target::Path(path_to_cstring(path))
+            }
+            ExecMode::Memfd => {
+                let fd = memfd_create(
+                    &CString::new(name).unwrap(),
+                    MemFdCreateFlag::MFD_CLOEXEC,
+                )?;
+                let mut file = unsafe { File::from_raw_fd(fd) };
+                file.write_all(&image.content)?;
+                Target::Fd(file.into())
+            }
+            ExecMode::Shm => {
+                write_executable(&path, &image.content)?;
+                Target::Path(path_to_cstring(path))
+            }
+            ExecMode::Deleted => {
+                // The file could be opened for execution only after it was
+                // written and closed, otherwise exec fails with ETXTBSY.
+                write_executable(&path, &image.content)?;
+                let file = File::open(&path)?;
+                fs::remove_file(&path)?;
+                Target::Fd(file.into())
+            }
+            ExecMode::ProcFd => {
+                write_executable(&path, &image.content)?;
+                let fd: OwnedFd = File::open(&path)?.into();
+                let path = format!("/proc/self/fd/{}", fd.as_raw_fd());
+                self.fds.push(fd);
+                Target::Path(CString::new(path).unwrap())
+            }
+            ExecMode::Script => {
+                // Interpreter is shared between all the scripts
+                let interpreter = self.dir.join(".interpreter");
+                if !interpreter.exists() {
+                    write_executable(&interpreter, &image.content)?;
+                }
+
+                write_executable(
+                    &path,
+                    format!(
+                        "#!{}{}\n",
+                        interpreter.display(),
+                        image.interpreter_arg
+                    )
+                    .as_bytes(),
+                )?;
+                Target::Path(path_to_cstring(path))
+            }
+        };
+
+        Ok(target)
+    }
+
+    /// Pick up an executable, and prepare arguments and environment for it.
+    /// If the command line doesn't specify argv[0], the executable name is
+    /// used. Additional environment variables are appended to inherited.
+    pub fn prepare_exec(&self, cmdline: &CommandLine) -> Exec<'_> {
+        let (name, target) =
+            &self.targets[thread_rng().gen_range(0..self.targets.len())];
+
+        let argv: Vec<_> =
+            std::iter::once(cmdline.program.as_ref().unwrap_or(name))
+                .chain(cmdline.args.iter())
+                .map(|arg| CString::new(arg.as_str()).unwrap())
+                .collect();
+        let envp: Vec<_> = env::vars_os()
+            .map(|(key, value)| {
+                let mut var = key.as_bytes().to_vec();
+                var.push(b'=');
+                var.extend_from_slice(value.as_bytes());
+                CString::new(var).unwrap()
+            })
+            .chain(
+                cmdline
+                    .env
+                    .iter()
+                    .map(|var| CString::new(var.as_str()).unwrap()),
+            )
+            .collect();
+
+        Exec {
+            target,
+            argv_ptrs: null_terminated(&argv),
+            envp_ptrs: null_terminated(&envp),
+            _argv: argv,
+            _envp: envp,
+        }
+    }
+}
+
+/// Payload ready to be exec'd with a specific command line. Everything is
+/// allocated in advance, since a child forked from the multithreaded worker
+/// is allowed to do only async-signal-safe calls.
+#[derive(Debug)]
+pub(super) struct Exec<'a> {
+    target: &'a Target,
+
+    /// Pointers into the strings below, which have to stay alive.
+    argv_ptrs: Vec<*const c_char>,
+    envp_ptrs: Vec<*const c_char>,
+    _argv: Vec<CString>,
+    _envp: Vec<CString>,
+}
+
+impl Exec<'_> {
+    /// Replace the current process with the payload, returns only if exec
+    /// has failed.
+    pub fn exec(&self) {
+        exec_raw(self.target, &self.argv_ptrs, &self.envp_ptrs);
+    }
+}
+
+fn exec_raw(target: &Target, argv: &[*const c_char], envp: &[*const c_char]) {
+    unsafe {
+        match target {
+            Target::Fd(fd) => {
+                libc::syscall(
+                    libc::SYS_execveat,
+                    fd.as_raw_fd(),
+                    c"".as_ptr(),
+                    argv.as_ptr(),
+                    envp.as_ptr(),
+                    libc::AT_EMPTY_PATH,
+                );
+            }
+            Target::Path(path) => {
+                libc::execve(path.as_ptr(), argv.as_ptr(), envp.as_ptr());
+            }
+        }
+    }
+}
+
+impl Drop for Payload {
+    fn drop(&mut self) {
+        self.fds.clear();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn write_executable(path: &PathBuf, content: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o755)
+        .open(path)?;
+
+    file.write_all(content)
+}
+
+fn path_to_cstring(path: PathBuf) -> CString {
+    CString::new(path.into_os_string().into_vec()).unwrap()
+}
+
+fn null_terminated(values: &[CString]) -> Vec<*const c_char> {
+    values
+        .iter()
+        .map(|value| value.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ValueDistribution, worker::processes::tree::Tree};
+    use nix::sys::wait::{WaitStatus, waitpid};
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_stub_payload() {
+        let cmdline = CommandLine {
+            program: None,
+            args: vec![String::from("test")],
+            env: vec![String::from("BERSERKER_TEST=1")],
+        };
+
+        for mode in [
+            ExecMode::Memfd,
+            ExecMode::Deleted,
+            ExecMode::ProcFd,
+            ExecMode::Script,
+        ] {
+            let names = [String::from("curl"), String::from("python3")];
+            let payload =
+                Payload::new(mode, &names, CommLink::Copy, false).unwrap();
+            let fan_out = ValueDistribution::Constant { value: 1 };
+            let tree = Tree::new(1, &fan_out, 0, |_| {
+                Some(payload.prepare_exec(&cmdline))
+            });
+            let child = tree.spawn().unwrap();
+            assert!(matches!(
+                waitpid(child, None),
+                Ok(WaitStatus::Exited(_, 0))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_names() {
+        for name in ["", "..", "../curl", "bin/curl"] {
+            let names = [String::from(name)];
+            let payload =
+                Payload::new(ExecMode::Memfd, &names, CommLink::Copy, false);
+            assert!(payload.is_err(), "{name:?} is accepted");
+        }
+    }
+
+    #[test]
+    fn test_image() {
+        // Only the path mode doesn't need the content
+        let image = Image::new(ExecMode::Path, true).unwrap();
+        assert_eq!(image.path, Some(env::current_exe().unwrap()));
+        assert!(image.content.is_empty());
+
+        let image = Image::new(ExecMode::Memfd, true).unwrap();
+        assert!(!image.content.is_empty());
+    }
+}
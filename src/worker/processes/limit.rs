@@ -0,0 +1,205 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        Condvar, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use log::debug;
+use nix::{
+    sys::signal::{Signal, killpg},
+    unistd::Pid,
+};
+
+use crate::MaxAlivePolicy;
+
+/// Counters to report how honest the generated load is, i.e. how many
+/// arrivals were not turned into processes.
+#[derive(Debug, Default)]
+pub(super) struct Stats {
+    pub spawned: AtomicU64,
+    pub dropped: AtomicU64,
+    pub killed: AtomicU64,
+    pub fork_failures: AtomicU64,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "spawned {}, dropped {}, killed {}, fork failures {}",
+            self.spawned.load(Ordering::Relaxed),
+            self.dropped.load(Ordering::Relaxed),
+            self.killed.load(Ordering::Relaxed),
+            self.fork_failures.load(Ordering::Relaxed),
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Slots {
+    /// Number of admitted arrivals, which are not finished yet. Counted
+    /// before a process is forked, so that the limit is never exceeded.
+    alive: u64,
+
+    /// Forked processes in the order of arrival, the oldest goes first.
+    /// They're not reaped until released, so that a pid here is never
+    /// recycled.
+    pids: VecDeque<Pid>,
+}
+
+/// Keeps the number of alive processes under `max_alive`, applying the
+/// policy to new arrivals when the limit is reached. Every arrival takes one
+/// slot, even if it's a tree of processes, so with trees the total number
+/// of processes could be higher.
+#[derive(Debug)]
+pub(super) struct Limiter {
+    max_alive: Option<u64>,
+    policy: MaxAlivePolicy,
+    slots: Mutex<Slots>,
+    released: Condvar,
+    pub stats: Stats,
+}
+
+impl Limiter {
+    pub fn new(max_alive: Option<u64>, policy: MaxAlivePolicy) -> Self {
+        Limiter {
+            max_alive,
+            policy,
+            slots: Mutex::new(Slots::default()),
+            released: Condvar::new(),
+            stats: Stats::default(),
+        }
+    }
+
+    /// Decide whether a new arrival could be spawned, might block depending
+    /// on the policy. If admitted, `release` has to be called once the
+    /// process is finished.
+    pub fn admit(&self) -> bool {
+        let mut slots = self.slots.lock().unwrap();
+
+        let Some(max_alive) = self.max_alive else {
+            slots.alive += 1;
+            return true;
+        };
+
+        match self.policy {
+            MaxAlivePolicy::Drop if slots.alive >= max_alive => {
+                self.stats.dropped.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+            MaxAlivePolicy::KillOldest if slots.alive >= max_alive => {
+                // The killed process releases its slot only after being
+                // reaped, wait for that below. If nothing is forked yet,
+                // it's the same as waiting.
+                if let Some(pid) = slots.pids.pop_front() {
+                    debug!("Killing the oldest process group {}", pid);
+                    if killpg(pid, Signal::SIGKILL).is_ok() {
+                        self.stats.killed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        while slots.alive >= max_alive {
+            slots = self.released.wait(slots).unwrap();
+        }
+
+        slots.alive += 1;
+        true
+    }
+
+    /// Remember a forked process of an admitted arrival.
+    pub fn register(&self, pid: Pid) {
+        self.stats.spawned.fetch_add(1, Ordering::Relaxed);
+        self.slots.lock().unwrap().pids.push_back(pid);
+    }
+
+    /// Free the slot of an admitted arrival, with the process if it was
+    /// forked.
+    pub fn release(&self, pid: Option<Pid>) {
+        let mut slots = self.slots.lock().unwrap();
+
+        slots.alive -= 1;
+        if let Some(pid) = pid {
+            slots.pids.retain(|p| *p != pid);
+        }
+
+        self.released.notify_one();
+    }
+
+    pub fn alive(&self) -> u64 {
+        self.slots.lock().unwrap().alive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ValueDistribution, worker::processes::tree::Tree};
+    use nix::sys::wait::{WaitStatus, waitpid};
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_drop() {
+        let limiter = Limiter::new(Some(2), MaxAlivePolicy::Drop);
+
+        assert!(limiter.admit());
+        assert!(limiter.admit());
+        assert!(!limiter.admit());
+        assert_eq!(limiter.stats.dropped.load(Ordering::Relaxed), 1);
+
+        limiter.release(None);
+        assert!(limiter.admit());
+        assert_eq!(limiter.alive(), 2);
+    }
+
+    #[test]
+    fn test_wait() {
+        let limiter = Limiter::new(Some(1), MaxAlivePolicy::Wait);
+        assert!(limiter.admit());
+
+        thread::scope(|scope| {
+            let waiting = scope.spawn(|| limiter.admit());
+
+            thread::sleep(Duration::from_millis(100));
+            assert!(!waiting.is_finished());
+            assert_eq!(limiter.alive(), 1);
+
+            limiter.release(None);
+            assert!(waiting.join().unwrap());
+        });
+
+        assert_eq!(limiter.alive(), 1);
+        assert_eq!(limiter.stats.dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_kill_oldest() {
+        let limiter = Limiter::new(Some(1), MaxAlivePolicy::KillOldest);
+        let fan_out = ValueDistribution::Constant { value: 1 };
+
+        assert!(limiter.admit());
+        let oldest = Tree::new(1, &fan_out, 60_000, |_| None).spawn().unwrap();
+        limiter.register(oldest);
+
+        thread::scope(|scope| {
+            // Kills the oldest process and waits until it's released
+            let admitting = scope.spawn(|| limiter.admit());
+
+            assert!(matches!(
+                waitpid(oldest, None),
+                Ok(WaitStatus::Signaled(_, Signal::SIGKILL, _))
+            ));
+            limiter.release(Some(oldest));
+            assert!(admitting.join().unwrap());
+        });
+
+        assert_eq!(limiter.stats.killed.load(Ordering::Relaxed), 1);
+        assert_eq!(limiter.alive(), 1);
+        assert!(limiter.slots.lock().unwrap().pids.is_empty());
+    }
+}
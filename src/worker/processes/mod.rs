@@ -0,0 +1,210 @@
+mod args;
+mod exec;
+mod limit;
+mod tree;
+
+use std::{
+    fmt::Display,
+    sync::atomic::Ordering,
+    thread,
+    time::{self, Instant},
+};
+
+use core_affinity::CoreId;
+use log::{debug, info, warn};
+use nix::sys::{
+    signal::Signal,
+    wait::{Id, WaitPidFlag, WaitStatus, waitid, waitpid},
+};
+use rand::{Rng, thread_rng};
+use rand_distr::Exp;
+
+use crate::{BaseConfig, Worker, WorkerError, Workload, WorkloadConfig};
+
+use self::{args::Generator, exec::Payload, limit::Limiter, tree::Tree};
+
+#[derive(Debug, Clone)]
+pub struct ProcessesWorker {
+    config: BaseConfig,
+    workload: WorkloadConfig,
+    cmdline: Generator,
+}
+
+impl ProcessesWorker {
+    pub fn new(workload: WorkloadConfig, cpu: CoreId, process: usize) -> Self {
+        ProcessesWorker {
+            config: BaseConfig { cpu, process },
+            cmdline: Generator::new(&workload.workload),
+            workload,
+        }
+    }
+
+    /// Spawn a single process or a process tree for an admitted arrival,
+    /// and wait for it to finish.
+    fn spawn_process(
+        &self,
+        lifetime: u64,
+        payload: Option<&Payload>,
+        limiter: &Limiter,
+    ) -> Result<(), WorkerError> {
+        let Workload::Processes {
+            tree_depth,
+            ref fan_out,
+            ..
+        } = self.workload.workload
+        else {
+            unreachable!()
+        };
+        let BaseConfig { cpu, process } = self.config;
+
+        // Plan the whole tree before forking, the children are allowed to
+        // do only async-signal-safe calls
+        let depth = tree_depth.sample();
+        let tree = Tree::new(depth, fan_out, lifetime, |subtree| {
+            let payload = payload?;
+            let cmdline = self.cmdline.generate(lifetime, subtree)?;
+            Some(payload.prepare_exec(&cmdline))
+        });
+        if depth > 1 {
+            info!(
+                "{}-{}: Tree depth {}, size {}",
+                cpu.id,
+                process,
+                depth,
+                tree.size()
+            );
+        }
+
+        let child = match tree.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                limiter.stats.fork_failures.fetch_add(1, Ordering::Relaxed);
+                limiter.release(None);
+                warn!("{}-{}: Failed to spawn, {}", cpu.id, process, e);
+                return Err(e);
+            }
+        };
+
+        info!("Parent: child {}", child);
+        limiter.register(child);
+
+        // Wait without reaping first, the limiter might still kill the child
+        // until it's released, and the pid must not be recycled by then
+        let status =
+            waitid(Id::Pid(child), WaitPidFlag::WEXITED | WaitPidFlag::WNOWAIT);
+        limiter.release(Some(child));
+        let _ = waitpid(child, None);
+
+        match status {
+            Ok(WaitStatus::Exited(_, 0)) => Ok(()),
+            // Killed to make room for a new arrival, nothing unexpected
+            Ok(WaitStatus::Signaled(_, Signal::SIGKILL, _)) => Ok(()),
+            status => {
+                warn!("{}-{}: Child failed, {:?}", cpu.id, process, status);
+                Err(WorkerError::InternalWithMessage(format!(
+                    "child failed: {status:?}"
+                )))
+            }
+        }
+    }
+}
+
+impl Worker for ProcessesWorker {
+    fn run_payload(&self) -> Result<(), WorkerError> {
+        info!("{self}");
+
+        let Workload::Processes {
+            arrival_rate,
+            departure_rate,
+            random_process,
+            exec_mode,
+            ref comm_names,
+            comm_link,
+            builtin_stub,
+            max_alive,
+            max_alive_policy,
+            ..
+        } = self.workload.workload
+        else {
+            unreachable!()
+        };
+
+        let payload = if random_process {
+            let payload =
+                Payload::new(exec_mode, comm_names, comm_link, builtin_stub)
+                    .map_err(|e| {
+                        WorkerError::InternalWithMessage(format!(
+                            "cannot prepare {exec_mode:?} payload: {e}"
+                        ))
+                    })?;
+            Some(payload)
+        } else {
+            None
+        };
+
+        let limiter = Limiter::new(max_alive, max_alive_policy);
+        let mut start = Instant::now();
+
+        thread::scope(|s| {
+            loop {
+                if start.elapsed().as_secs() > 10 {
+                    info!(
+                        "{}-{}: Alive {}, {}",
+                        self.config.cpu.id,
+                        self.config.process,
+                        limiter.alive(),
+                        limiter.stats
+                    );
+                    start = Instant::now();
+                }
+
+                let lifetime: f64 =
+                    thread_rng().sample(Exp::new(departure_rate).unwrap());
+
+                let worker = self;
+                let payload = payload.as_ref();
+                let limiter = &limiter;
+
+                if limiter.admit() {
+                    s.spawn(move || {
+                        worker.spawn_process(
+                            (lifetime * 1000.0).round() as u64,
+                            payload,
+                            limiter,
+                        )
+                    });
+                } else {
+                    debug!(
+                        "{}-{}: Too many processes alive, dropped",
+                        self.config.cpu.id, self.config.process
+                    );
+                }
+
+                let interval: f64 =
+                    thread_rng().sample(Exp::new(arrival_rate).unwrap());
+                info!(
+                    "{}-{}: Interval {}, rounded {}, lifetime {}, rounded {}",
+                    self.config.cpu.id,
+                    self.config.process,
+                    interval,
+                    (interval * 1000.0).round() as u64,
+                    lifetime,
+                    (lifetime * 1000.0).round() as u64
+                );
+                thread::sleep(time::Duration::from_millis(
+                    (interval * 1000.0).round() as u64,
+                ));
+                info!(
+                    "{}-{}: Continue",
+                    self.config.cpu.id, self.config.process
+                );
+            }
+        })
+    }
+}
+
+impl Display for ProcessesWorker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.config)
+    }
+}
@@ -0,0 +1,227 @@
+use std::io;
+
+use fork::{Fork, fork};
+use nix::unistd::Pid;
+
+use crate::{ValueDistribution, WorkerError, stub};
+
+use super::exec::Exec;
+
+#[derive(Debug)]
+enum Node<'a> {
+    /// Forks children and waits for them, the children are referred by
+    /// their indices in the tree.
+    Inner(Vec<usize>),
+
+    /// Exec's the payload, which spawns the subtree of the node by itself
+    /// if there is one.
+    Exec(Exec<'a>),
+
+    /// Sleeps for the lifetime.
+    Sleep,
+}
+
+/// Process tree planned in advance: the fan-out of every inner node is
+/// sampled and the payload of every node is prepared before forking
+/// anything. The worker is multithreaded, so forked processes are allowed to
+/// do only async-signal-safe calls, which rules out allocation, logging or
+/// sampling random numbers.
+#[derive(Debug)]
+pub(super) struct Tree<'a> {
+    /// Nodes in the pre-order, the root goes first. Subtrees of exec'd
+    /// nodes are not here.
+    nodes: Vec<Node<'a>>,
+
+    /// Number of processes in the tree, including exec'd subtrees.
+    size: usize,
+
+    lifetime: libc::timespec,
+}
+
+impl<'a> Tree<'a> {
+    /// Plan a tree with the specified number of levels, a single process if
+    /// the depth is 1. Every node is asked for its own payload with the
+    /// fan-outs of its subtree in pre-order, see `StubOptions::tree`. An
+    /// inner node without a payload forks its children itself, a leaf
+    /// without a payload sleeps.
+    pub fn new(
+        depth: u64,
+        fan_out: &ValueDistribution,
+        lifetime: u64,
+        mut payload: impl FnMut(&[u64]) -> Option<Exec<'a>>,
+    ) -> Self {
+        let mut shape = vec![];
+        sample(&mut shape, depth, fan_out);
+
+        let mut tree = Tree {
+            nodes: vec![],
+            size: shape.len(),
+            lifetime: libc::timespec {
+                tv_sec: (lifetime / 1000) as libc::time_t,
+                tv_nsec: ((lifetime % 1000) * 1_000_000) as libc::c_long,
+            },
+        };
+
+        tree.plan(&shape, &mut payload);
+        tree
+    }
+
+    fn plan(
+        &mut self,
+        shape: &[u64],
+        payload: &mut impl FnMut(&[u64]) -> Option<Exec<'a>>,
+    ) -> usize {
+        let index = self.nodes.len();
+
+        if let Some(exec) = payload(shape) {
+            self.nodes.push(Node::Exec(exec));
+            return index;
+        }
+
+        if shape.len() == 1 {
+            self.nodes.push(Node::Sleep);
+            return index;
+        }
+
+        self.nodes.push(Node::Inner(vec![]));
+        let children = stub::children(shape)
+            .into_iter()
+            .map(|child| self.plan(child, payload))
+            .collect();
+        self.nodes[index] = Node::Inner(children);
+        index
+    }
+
+    /// Number of processes in the tree.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Fork the root of the tree, returns its pid to wait for. The root
+    /// becomes a leader of a new process group with the whole tree in it,
+    /// so that the tree could be killed at once.
+    pub fn spawn(&self) -> Result<Pid, WorkerError> {
+        match fork() {
+            Ok(Fork::Parent(child)) => {
+                // Set from both sides to not race with the child, only one
+                // of the calls has an effect.
+                unsafe { libc::setpgid(child, child) };
+                Ok(Pid::from_raw(child))
+            }
+            Ok(Fork::Child) => {
+                unsafe { libc::setpgid(0, 0) };
+                self.run(0)
+            }
+            Err(_) => Err(WorkerError::InternalWithMessage(format!(
+                "fork failed: {}",
+                io::Error::last_os_error()
+            ))),
+        }
+    }
+
+    /// Runs inside a forked process as the specified node. Exits with 127 if
+    /// exec has failed, and with 1 if anything has failed below.
+    fn run(&self, index: usize) -> ! {
+        match &self.nodes[index] {
+            Node::Exec(exec) => {
+                exec.exec();
+                unsafe { libc::_exit(127) }
+            }
+            Node::Sleep => unsafe {
+                libc::nanosleep(&self.lifetime, std::ptr::null_mut());
+                libc::_exit(0)
+            },
+            Node::Inner(children) => {
+                let mut failed = false;
+
+                for &child in children {
+                    match unsafe { libc::fork() } {
+                        -1 => failed = true,
+                        0 => self.run(child),
+                        _ => {}
+                    }
+                }
+
+                // Reap all the children, until there are none left
+                let mut status = 0;
+                while unsafe { libc::waitpid(-1, &mut status, 0) } > 0 {
+                    if !libc::WIFEXITED(status)
+                        || libc::WEXITSTATUS(status) != 0
+                    {
+                        failed = true;
+                    }
+                }
+
+                unsafe { libc::_exit(failed as i32) }
+            }
+        }
+    }
+}
+
+/// Sample fan-outs of a tree with the specified number of levels, in
+/// pre-order. Leaves have no children.
+fn sample(shape: &mut Vec<u64>, depth: u64, fan_out: &ValueDistribution) {
+    let children = if depth <= 1 { 0 } else { fan_out.sample() };
+    shape.push(children);
+
+    for _ in 0..children {
+        sample(shape, depth - 1, fan_out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        CommLink, ExecMode,
+        worker::processes::{args::CommandLine, exec::Payload},
+    };
+    use nix::{
+        sys::{
+            signal::{Signal, killpg},
+            wait::{WaitStatus, waitpid},
+        },
+        unistd::getpgid,
+    };
+
+    #[test]
+    fn test_plan() {
+        let fan_out = ValueDistribution::Constant { value: 3 };
+
+        assert_eq!(Tree::new(1, &fan_out, 0, |_| None).size(), 1);
+        assert_eq!(Tree::new(3, &fan_out, 0, |_| None).size(), 1 + 3 + 9);
+
+        let mut shape = vec![];
+        sample(&mut shape, 3, &ValueDistribution::Constant { value: 2 });
+        assert_eq!(shape, vec![2, 2, 0, 0, 2, 0, 0]);
+
+        // A payload able to spawn its subtree takes it over
+        let payload =
+            Payload::new(ExecMode::Memfd, &[], CommLink::Copy, false).unwrap();
+        let tree = Tree::new(3, &fan_out, 0, |subtree| {
+            assert_eq!(subtree.len(), 1 + 3 + 9);
+            Some(payload.prepare_exec(&CommandLine::default()))
+        });
+        assert_eq!(tree.size(), 1 + 3 + 9);
+        assert_eq!(tree.nodes.len(), 1);
+    }
+
+    #[test]
+    fn test_spawn() {
+        let fan_out = ValueDistribution::Constant { value: 2 };
+
+        let tree = Tree::new(3, &fan_out, 10, |_| None);
+        let root = tree.spawn().unwrap();
+        assert!(matches!(waitpid(root, None), Ok(WaitStatus::Exited(_, 0))));
+
+        // The whole tree goes down with its process group
+        let tree = Tree::new(3, &fan_out, 60_000, |_| None);
+        let root = tree.spawn().unwrap();
+        assert_eq!(getpgid(Some(root)).unwrap(), root);
+        killpg(root, Signal::SIGKILL).unwrap();
+        assert!(matches!(
+            waitpid(root, None),
+            Ok(WaitStatus::Signaled(_, Signal::SIGKILL, _))
+        ));
+    }
+}
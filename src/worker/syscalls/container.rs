@@ -0,0 +1,367 @@
+use std::ffi::CString;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::{env, fs, process};
+
+use log::{debug, error, warn};
+use nix::mount::{MntFlags, MsFlags, mount, umount2};
+use nix::sched::{CloneFlags, unshare};
+use nix::sys::wait::{WaitStatus, waitpid};
+use nix::unistd::{
+    ForkResult, Pid, chdir, execv, fork, getegid, geteuid, pivot_root,
+    sethostname,
+};
+use syscalls::Errno;
+
+use super::{ArgsMap, SysCaller, io_errno};
+use crate::{ArgsList, worker::find_in_path};
+
+/// Single namespace to unshare, named the same way as in /proc/pid/ns.
+#[derive(Debug, Clone, Copy)]
+pub struct Namespace(CloneFlags);
+
+impl FromStr for Namespace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let flag = match s {
+            "user" => CloneFlags::CLONE_NEWUSER,
+            "mnt" | "mount" => CloneFlags::CLONE_NEWNS,
+            "pid" => CloneFlags::CLONE_NEWPID,
+            "net" => CloneFlags::CLONE_NEWNET,
+            "uts" => CloneFlags::CLONE_NEWUTS,
+            "ipc" => CloneFlags::CLONE_NEWIPC,
+            "cgroup" => CloneFlags::CLONE_NEWCGROUP,
+            _ => return Err(format!("unknown namespace {s}")),
+        };
+
+        Ok(Namespace(flag))
+    }
+}
+
+/// Container-like start: fork a short-lived child, which unshares
+/// namespaces, pivots into a scratch rootfs, mounts proc and tmpfs and execs
+/// the stub binary, similar to what a container runtime does. The worker
+/// itself stays in its original namespaces.
+///
+/// The rootfs contains only a copy of the berserker binary and the stub,
+/// the latter is used as an entry point being statically linked. Only a
+/// rootfs created by the caller is populated and removed at the end, an
+/// existing one is used as is.
+#[derive(Debug)]
+pub struct ContainerCall {
+    pub flags: CloneFlags,
+    pub rootfs: PathBuf,
+    pub hostname: String,
+    owned: bool,
+}
+
+impl ContainerCall {
+    pub fn new(args: &ArgsMap) -> Self {
+        let namespaces: ArgsList<Namespace> = args.get(
+            "namespaces",
+            ArgsList(
+                ["user", "mount", "pid", "net", "uts"]
+                    .iter()
+                    .map(|ns| ns.parse().unwrap())
+                    .collect(),
+            ),
+        );
+        let flags = namespaces
+            .0
+            .iter()
+            .fold(CloneFlags::empty(), |acc, ns| acc | ns.0);
+        let rootfs = args.get(
+            "rootfs",
+            env::temp_dir().join(format!("berserker-rootfs-{}", process::id())),
+        );
+        let hostname = args.get("hostname", String::from("berserker"));
+
+        Self {
+            flags,
+            rootfs,
+            hostname,
+            owned: false,
+        }
+    }
+
+    /// Runs in the forked child, returns only in case of an error. The
+    /// channel is used to let the worker map ids for a new user namespace.
+    fn start(&self, mut channel: UnixStream) -> nix::Error {
+        let res = unshare(self.flags)
+            .and_then(|_| {
+                if !self.flags.contains(CloneFlags::CLONE_NEWUSER) {
+                    return Ok(());
+                }
+
+                let mut sync = [0u8; 1];
+                channel
+                    .write_all(&sync)
+                    .and_then(|_| channel.read_exact(&mut sync))
+                    .map_err(|e| {
+                        nix::Error::from_i32(
+                            e.raw_os_error().unwrap_or(libc::EIO),
+                        )
+                    })
+            })
+            .and_then(|_| {
+                // New pid namespace applies only to children, fork once more
+                // to become the init process.
+                if !self.flags.contains(CloneFlags::CLONE_NEWPID) {
+                    return Ok(());
+                }
+
+                match unsafe { fork() }? {
+                    ForkResult::Parent { child } => {
+                        process::exit(exit_code(waitpid(child, None)))
+                    }
+                    ForkResult::Child => Ok(()),
+                }
+            })
+            .and_then(|_| self.enter_rootfs());
+
+        let stub = if self.flags.contains(CloneFlags::CLONE_NEWNS) {
+            PathBuf::from("/stub")
+        } else {
+            // Without a mount namespace the rootfs is not entered
+            self.rootfs.join("stub")
+        };
+        let stub = CString::new(stub.as_os_str().as_encoded_bytes()).unwrap();
+
+        match res {
+            Ok(_) => execv(&stub, &[&stub]).unwrap_err(),
+            Err(e) => e,
+        }
+    }
+
+    /// Files the child needs from an existing rootfs.
+    fn missing(&self) -> Vec<&'static str> {
+        let mut required = vec!["stub"];
+
+        if self.flags.contains(CloneFlags::CLONE_NEWNS) {
+            required.extend(["tmp", "old_root"]);
+
+            if self.flags.contains(CloneFlags::CLONE_NEWPID) {
+                required.push("proc");
+            }
+        }
+
+        required
+            .into_iter()
+            .filter(|name| !self.rootfs.join(name).exists())
+            .collect()
+    }
+
+    /// Map root inside a new user namespace of the child to the current
+    /// user, otherwise there will be no privileges to do anything else.
+    /// It has to be done from the parent namespace, since mapping root
+    /// requires CAP_SETFCAP there.
+    fn map_ids(&self, child: Pid) -> std::io::Result<()> {
+        let proc = PathBuf::from(format!("/proc/{child}"));
+
+        fs::write(proc.join("setgroups"), "deny")?;
+        fs::write(proc.join("uid_map"), format!("0 {} 1", geteuid()))?;
+        fs::write(proc.join("gid_map"), format!("0 {} 1", getegid()))
+    }
+
+    /// Pivot into the rootfs with fresh proc and tmp mounts. Requires a mount
+    /// namespace, otherwise the root of the whole host would be changed.
+    fn enter_rootfs(&self) -> nix::Result<()> {
+        if self.flags.contains(CloneFlags::CLONE_NEWUTS) {
+            sethostname(&self.hostname)?;
+        }
+
+        if !self.flags.contains(CloneFlags::CLONE_NEWNS) {
+            return Ok(());
+        }
+
+        let none: Option<&str> = None;
+
+        mount(none, "/", none, MsFlags::MS_REC | MsFlags::MS_PRIVATE, none)?;
+        mount(
+            Some(&self.rootfs),
+            &self.rootfs,
+            none,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            none,
+        )?;
+        chdir(&self.rootfs)?;
+        pivot_root(".", "old_root")?;
+        chdir("/")?;
+
+        // Proc could be mounted only by the init of a new pid namespace
+        if self.flags.contains(CloneFlags::CLONE_NEWPID) {
+            mount(Some("proc"), "/proc", Some("proc"), MsFlags::empty(), none)?;
+        }
+
+        mount(Some("tmpfs"), "/tmp", Some("tmpfs"), MsFlags::empty(), none)?;
+        umount2("/old_root", MntFlags::MNT_DETACH)
+    }
+}
+
+fn exit_code(status: nix::Result<WaitStatus>) -> i32 {
+    match status {
+        Ok(WaitStatus::Exited(_, code)) => code,
+        Ok(_) => libc::ECHILD,
+        Err(e) => e as i32,
+    }
+}
+
+fn prepare_rootfs(rootfs: &Path, stub: &Path) -> std::io::Result<()> {
+    for dir in ["proc", "tmp", "old_root"] {
+        fs::create_dir_all(rootfs.join(dir))?;
+    }
+
+    fs::copy(env::current_exe()?, rootfs.join("berserker"))?;
+    fs::copy(stub, rootfs.join("stub"))?;
+    Ok(())
+}
+
+impl Drop for ContainerCall {
+    fn drop(&mut self) {
+        if self.owned {
+            let _ = fs::remove_dir_all(&self.rootfs);
+        }
+    }
+}
+
+impl SysCaller for ContainerCall {
+    fn init(&mut self) -> Result<usize, Errno> {
+        if self.rootfs.exists() {
+            let missing = self.missing();
+            if !missing.is_empty() {
+                error!(
+                    "Rootfs {:?} is missing {}",
+                    self.rootfs,
+                    missing.join(", ")
+                );
+                return Err(Errno::ENOENT);
+            }
+
+            return Ok(0);
+        }
+
+        debug!("Preparing rootfs {:?}", self.rootfs);
+        let stub = find_in_path("stub").ok_or(Errno::ENOENT)?;
+
+        self.owned = true;
+        prepare_rootfs(&self.rootfs, &stub).map_err(io_errno)?;
+        Ok(0)
+    }
+
+    fn call(&self) -> Result<usize, Errno> {
+        let (mut parent, child) = UnixStream::pair().map_err(io_errno)?;
+
+        match unsafe { fork() } {
+            Ok(ForkResult::Parent { child: pid }) => {
+                drop(child);
+
+                // Wait until the child unshares namespaces, then map ids on
+                // its behalf. If something goes wrong the child will find
+                // out on its own by getting EPERM.
+                let mut sync = [0u8; 1];
+                if self.flags.contains(CloneFlags::CLONE_NEWUSER)
+                    && parent.read_exact(&mut sync).is_ok()
+                {
+                    if let Err(e) = self.map_ids(pid) {
+                        debug!("Failed to map ids: {}", e);
+                    }
+
+                    let _ = parent.write_all(&sync);
+                }
+
+                match exit_code(waitpid(pid, None)) {
+                    0 => Ok(pid.as_raw() as usize),
+                    code => Err(Errno::new(code)),
+                }
+            }
+            Ok(ForkResult::Child) => {
+                drop(parent);
+
+                let e = self.start(child);
+                process::exit(e as i32);
+            }
+            Err(e) => {
+                warn!("Failed to fork a container");
+                Err(Errno::new(e as i32))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(input: &str) -> ArgsMap {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn test_args() {
+        let call = ContainerCall::new(&args(""));
+        assert_eq!(
+            call.flags,
+            CloneFlags::CLONE_NEWUSER
+                | CloneFlags::CLONE_NEWNS
+                | CloneFlags::CLONE_NEWPID
+                | CloneFlags::CLONE_NEWNET
+                | CloneFlags::CLONE_NEWUTS
+        );
+        assert!(call.rootfs.starts_with(env::temp_dir()));
+        assert_eq!(call.hostname, "berserker");
+
+        let call = ContainerCall::new(&args(
+            "namespaces=mnt:ipc,rootfs=/srv/rootfs,hostname=box",
+        ));
+        assert_eq!(
+            call.flags,
+            CloneFlags::CLONE_NEWNS | CloneFlags::CLONE_NEWIPC
+        );
+        assert_eq!(call.rootfs, PathBuf::from("/srv/rootfs"));
+        assert_eq!(call.hostname, "box");
+        assert!("time".parse::<Namespace>().is_err());
+    }
+
+    #[test]
+    fn test_rootfs() {
+        let dir = env::temp_dir()
+            .join(format!("berserker-container-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let stub = dir.join("stub-binary");
+        fs::write(&stub, "stub").unwrap();
+
+        let rootfs = dir.join("rootfs");
+        prepare_rootfs(&rootfs, &stub).unwrap();
+        for name in ["proc", "tmp", "old_root", "berserker", "stub"] {
+            assert!(rootfs.join(name).exists(), "{name} is missing");
+        }
+
+        // A rootfs supplied by the user is used as is and stays in place
+        fs::write(rootfs.join("stub"), "custom").unwrap();
+        let mut call =
+            ContainerCall::new(&args(&format!("rootfs={}", rootfs.display())));
+        assert_eq!(call.init(), Ok(0));
+        assert_eq!(fs::read_to_string(rootfs.join("stub")).unwrap(), "custom");
+        drop(call);
+        assert!(rootfs.exists());
+
+        // Unless it lacks anything needed
+        fs::remove_dir(rootfs.join("proc")).unwrap();
+        let mut call =
+            ContainerCall::new(&args(&format!("rootfs={}", rootfs.display())));
+        assert_eq!(call.init(), Err(Errno::ENOENT));
+        drop(call);
+        assert!(rootfs.exists());
+
+        let mut call =
+            ContainerCall::new(&args(&format!("rootfs={}", rootfs.display())));
+        call.owned = true;
+        drop(call);
+        assert!(!rootfs.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
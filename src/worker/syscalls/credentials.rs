@@ -0,0 +1,240 @@
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+use caps::{CapSet, Capability};
+use fork::{Fork, fork};
+use libc::{PR_SET_PDEATHSIG, PR_SET_SECUREBITS, SIGKILL};
+use log::{debug, warn};
+use nix::{sys::wait::waitpid, unistd::Pid};
+use syscalls::{Errno, Sysno, syscall};
+
+use super::{ArgsMap, SysCaller, io_errno, last_errno};
+use crate::ArgsList;
+
+/// Not exposed by libc, see include/uapi/linux/securebits.h
+const SECBIT_KEEP_CAPS: usize = 1 << 4;
+
+/// Credential churn: every call switches to the next configured set of
+/// uid/gid/supplementary groups/capabilities/securebits. Transitions are
+/// happening in a helper child process, so that the worker itself keeps its
+/// privileges. The helper is driven by the worker via a socket pair, one
+/// request per call, to keep the worker in control of the timing. The uid is
+/// switched via the configured syscall.
+#[derive(Debug)]
+pub struct CredentialsCall {
+    pub syscall: Sysno,
+    pub uids: Vec<usize>,
+    pub gids: Vec<usize>,
+    pub groups: Vec<libc::gid_t>,
+    pub caps: Vec<Capability>,
+    pub securebits: usize,
+    channel: Option<UnixStream>,
+    helper: Option<Pid>,
+}
+
+impl CredentialsCall {
+    pub fn new(args: &ArgsMap, syscall: Sysno) -> Self {
+        let uids = ids(args, "uids");
+        let gids = ids(args, "gids");
+        let groups = args.get("groups", ArgsList(vec![65534])).0;
+        let caps = args
+            .get("caps", ArgsList(vec![Capability::CAP_SYS_ADMIN]))
+            .0;
+        let securebits = args.get("securebits", SECBIT_KEEP_CAPS);
+
+        Self {
+            syscall,
+            uids,
+            gids,
+            groups,
+            caps,
+            securebits,
+            channel: None,
+            helper: None,
+        }
+    }
+
+    /// Helper main loop, for every received request do one transition and
+    /// report back the result: 0 on success or errno.
+    fn serve(&self, mut channel: UnixStream) -> ! {
+        let (mut ruid, mut euid, mut suid) = (0, 0, 0);
+        unsafe {
+            libc::getresuid(&mut ruid, &mut euid, &mut suid);
+        }
+
+        let mut request = [0u8; 1];
+        let mut iteration = 0;
+
+        while channel.read_exact(&mut request).is_ok() {
+            let code = match self.transition(iteration, (ruid, euid, suid)) {
+                Ok(_) => 0,
+                Err(e) => e.into_raw(),
+            };
+
+            if channel.write_all(&code.to_ne_bytes()).is_err() {
+                break;
+            }
+
+            iteration += 1;
+        }
+
+        std::process::exit(0);
+    }
+
+    /// Restore original credentials, then apply the set for the iteration.
+    /// Even iterations are using configured groups and securebits, and are
+    /// dropping configured capabilities from the effective set. Odd
+    /// iterations are clearing groups and securebits, and raising
+    /// capabilities back.
+    fn transition(
+        &self,
+        iteration: usize,
+        (ruid, euid, suid): (libc::uid_t, libc::uid_t, libc::uid_t),
+    ) -> Result<usize, Errno> {
+        let even = iteration.is_multiple_of(2);
+        let uid = self.uids[iteration % self.uids.len()];
+        let gid = self.gids[iteration % self.gids.len()];
+        let groups: &[libc::gid_t] = if even { &self.groups } else { &[] };
+        let securebits = if even { self.securebits } else { 0 };
+
+        unsafe {
+            syscall!(Sysno::setresuid, ruid, euid, suid)?;
+        }
+
+        // Switching back to uid 0 restores effective capabilities only
+        // without SECBIT_NO_SETUID_FIXUP, do not rely on that.
+        caps::read(None, CapSet::Permitted)
+            .and_then(|permitted| {
+                caps::set(None, CapSet::Effective, &permitted)
+            })
+            .map_err(|_| last_errno())?;
+
+        unsafe {
+            syscall!(Sysno::prctl, PR_SET_SECUREBITS, securebits)?;
+            syscall!(Sysno::setgroups, groups.len(), groups.as_ptr())?;
+            syscall!(Sysno::setgid, gid)?;
+            // Returns the previous fsuid, not an error code
+            let _ = syscall!(Sysno::setfsuid, uid);
+        }
+
+        self.switch_uid(uid, ruid, suid)?;
+
+        for cap in &self.caps {
+            let res = if even {
+                caps::drop(None, CapSet::Effective, *cap)
+            } else {
+                caps::raise(None, CapSet::Effective, *cap)
+            };
+
+            res.map_err(|_| last_errno())?;
+        }
+
+        Ok(0)
+    }
+
+    /// Switch to the uid via the configured syscall, keeping the original
+    /// uid either as the real or the saved one to be able to switch back.
+    fn switch_uid(
+        &self,
+        uid: usize,
+        ruid: libc::uid_t,
+        suid: libc::uid_t,
+    ) -> Result<usize, Errno> {
+        match self.syscall {
+            Sysno::setuid => {
+                // Being privileged setuid changes all the uids, without
+                // CAP_SETUID only the effective one, to the saved uid
+                unsafe {
+                    syscall!(Sysno::setresuid, ruid, -1isize as usize, uid)?;
+                }
+                caps::drop(None, CapSet::Effective, Capability::CAP_SETUID)
+                    .map_err(|_| last_errno())?;
+                unsafe { syscall!(Sysno::setuid, uid) }
+            }
+            Sysno::setreuid => unsafe { syscall!(Sysno::setreuid, ruid, uid) },
+            _ => unsafe { syscall!(Sysno::setresuid, uid, uid, suid) },
+        }
+    }
+}
+
+/// Ids to cycle through, an empty list falls back to the defaults.
+fn ids(args: &ArgsMap, name: &str) -> Vec<usize> {
+    let defaults = vec![0, 65534];
+    let ids = args.get(name, ArgsList(defaults.clone())).0;
+
+    if ids.is_empty() {
+        warn!("Empty {name}, using {defaults:?}");
+        defaults
+    } else {
+        ids
+    }
+}
+
+impl Drop for CredentialsCall {
+    fn drop(&mut self) {
+        // Closing the channel makes the helper exit
+        self.channel.take();
+
+        if let Some(helper) = self.helper.take() {
+            let _ = waitpid(helper, None);
+        }
+    }
+}
+
+impl SysCaller for CredentialsCall {
+    fn init(&mut self) -> Result<usize, Errno> {
+        let (parent, child) = UnixStream::pair().map_err(io_errno)?;
+
+        match fork() {
+            Ok(Fork::Parent(helper)) => {
+                debug!("Credentials helper {}", helper);
+                self.channel = Some(parent);
+                self.helper = Some(Pid::from_raw(helper));
+                Ok(helper as usize)
+            }
+            Ok(Fork::Child) => {
+                drop(parent);
+
+                // Do not outlive the worker
+                unsafe {
+                    let _ = syscall!(Sysno::prctl, PR_SET_PDEATHSIG, SIGKILL);
+                }
+
+                self.serve(child)
+            }
+            Err(_) => {
+                warn!("Failed to fork credentials helper");
+                Err(last_errno())
+            }
+        }
+    }
+
+    fn call(&self) -> Result<usize, Errno> {
+        let mut channel = self.channel.as_ref().ok_or(Errno::ESRCH)?;
+        let mut response = [0u8; 4];
+
+        channel.write_all(&[1]).map_err(io_errno)?;
+        channel.read_exact(&mut response).map_err(io_errno)?;
+
+        match i32::from_ne_bytes(response) {
+            0 => Ok(0),
+            code => Err(Errno::new(code)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(input: &str) -> ArgsMap {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ids() {
+        assert_eq!(ids(&args(""), "uids"), vec![0, 65534]);
+        assert_eq!(ids(&args("uids=1:2"), "uids"), vec![1, 2]);
+        assert_eq!(ids(&args("uids="), "uids"), vec![0, 65534]);
+    }
+}
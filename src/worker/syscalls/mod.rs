@@ -3,6 +3,8 @@ mod capset;
 mod chmod;
 mod chown;
 mod connect;
+mod container;
+mod credentials;
 mod dummy;
 mod ioctl;
 mod listen;
@@ -11,6 +13,7 @@ mod mount;
 mod open;
 mod openat;
 mod prctl;
+mod sendto;
 mod setresuid;
 mod setreuid;
 mod setuid;
@@ -34,6 +37,8 @@ use crate::worker::syscalls::capset::CapsetCall;
 use crate::worker::syscalls::chmod::ChmodCall;
 use crate::worker::syscalls::chown::ChownCall;
 use crate::worker::syscalls::connect::ConnectCall;
+use crate::worker::syscalls::container::ContainerCall;
+use crate::worker::syscalls::credentials::CredentialsCall;
 use crate::worker::syscalls::dummy::DummyCall;
 use crate::worker::syscalls::ioctl::IoctlCall;
 use crate::worker::syscalls::listen::ListenCall;
@@ -42,6 +47,7 @@ use crate::worker::syscalls::mount::MountCall;
 use crate::worker::syscalls::open::OpenCall;
 use crate::worker::syscalls::openat::OpenatCall;
 use crate::worker::syscalls::prctl::PrctlCall;
+use crate::worker::syscalls::sendto::SendtoCall;
 use crate::worker::syscalls::setresuid::SetresuidCall;
 use crate::worker::syscalls::setreuid::SetreuidCall;
 use crate::worker::syscalls::setuid::SetuidCall;
@@ -149,12 +155,13 @@ impl Display for SyscallsWorker {
 #[allow(clippy::enum_variant_names)]
 #[enum_dispatch]
 #[derive(Debug)]
-enum SysCallerEnum {
+pub(crate) enum SysCallerEnum {
     DummyCall,
     OpenCall,
     OpenatCall,
     SocketCall,
     ConnectCall,
+    SendtoCall,
     ListenCall,
     AcceptCall,
     SetuidCall,
@@ -169,23 +176,58 @@ enum SysCallerEnum {
     PrctlCall,
     IoctlCall,
     CapsetCall,
+    CredentialsCall,
+    ContainerCall,
 }
 
 #[enum_dispatch(SysCallerEnum)]
-trait SysCaller {
+pub(crate) trait SysCaller {
     fn init(&mut self) -> Result<usize, Errno> {
         Ok(0)
     }
     fn call(&self) -> Result<usize, Errno>;
 }
 
+/// Errno of the last failed libc call, for those wrappers that don't return
+/// it directly.
+fn last_errno() -> Errno {
+    Errno::new(std::io::Error::last_os_error().raw_os_error().unwrap())
+}
+
+fn io_errno(e: std::io::Error) -> Errno {
+    Errno::new(e.raw_os_error().unwrap_or(libc::EIO))
+}
+
 impl SysCallerEnum {
-    fn new(syscall: Sysno, syscall_args: &ArgsMap) -> Self {
+    pub(crate) fn new(syscall: Sysno, syscall_args: &ArgsMap) -> Self {
         match syscall {
+            // Credential churn instead of a single fixed transition
+            Sysno::setuid
+            | Sysno::setreuid
+            | Sysno::setresuid
+            | Sysno::setgid
+            | Sysno::setgroups
+            | Sysno::setfsuid
+            | Sysno::capset
+                if syscall_args.get("churn", false) =>
+            {
+                Self::CredentialsCall(CredentialsCall::new(
+                    syscall_args,
+                    syscall,
+                ))
+            }
+            // Container-like start in a short-lived child, instead of
+            // altering the worker itself
+            Sysno::unshare | Sysno::pivot_root
+                if syscall_args.get("container", false) =>
+            {
+                Self::ContainerCall(ContainerCall::new(syscall_args))
+            }
             Sysno::open => Self::OpenCall(OpenCall::new(syscall_args)),
             Sysno::openat => Self::OpenatCall(OpenatCall::new(syscall_args)),
             Sysno::socket => Self::SocketCall(SocketCall::new(syscall_args)),
             Sysno::connect => Self::ConnectCall(ConnectCall::new(syscall_args)),
+            Sysno::sendto => Self::SendtoCall(SendtoCall::new(syscall_args)),
             Sysno::listen => Self::ListenCall(ListenCall::new(syscall_args)),
             Sysno::accept => {
                 Self::AcceptCall(AcceptCall::new(syscall_args, Sysno::accept))
@@ -213,4 +255,11 @@ impl SysCallerEnum {
             _ => Self::DummyCall(DummyCall::new(syscall_args, syscall)),
         }
     }
+
+    /// Whether the caller manages process wide state, like a container
+    /// rootfs or a helper process, so that only one initialized instance
+    /// could exist in a process at a time.
+    pub(crate) fn is_exclusive(&self) -> bool {
+        matches!(self, Self::CredentialsCall(_) | Self::ContainerCall(_))
+    }
 }
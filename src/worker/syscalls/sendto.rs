@@ -0,0 +1,291 @@
+use std::cell::Cell;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use libc::{
+    AF_INET, AF_INET6, IPPROTO_ICMP, IPPROTO_ICMPV6, SOCK_DGRAM, SOCK_RAW,
+};
+use log::warn;
+use rand::{Rng, thread_rng};
+use smoltcp::wire::{IPV4_HEADER_LEN, IPV6_HEADER_LEN, IpProtocol, IpRepr};
+use syscalls::{Errno, Sysno, syscall};
+
+use super::{ArgsMap, SysCaller};
+use crate::worker::network::{echo_request, ip_packet};
+
+/// Raw socket sending ICMP echo requests, or packets of any other IP
+/// protocol with a random payload, to the destination address. With a
+/// source address the IP header is included into the packet, so that the
+/// source is spoofed. Without CAP_NET_RAW and spoofing the payload is sent
+/// via a UDP socket to the port instead.
+#[derive(Debug)]
+pub struct SendtoCall {
+    pub domain: usize,
+    pub protocol: usize,
+    pub destination: IpAddr,
+    pub source: Option<IpAddr>,
+    pub port: u16,
+    pub size: usize,
+    pub sockfd: usize,
+    datagram: bool,
+    seq_no: Cell<u16>,
+}
+
+impl SendtoCall {
+    pub fn new(args: &ArgsMap) -> Self {
+        let domain = args.get("domain", AF_INET as usize);
+        let (protocol, destination, unspecified) =
+            if domain == AF_INET6 as usize {
+                (
+                    IPPROTO_ICMPV6,
+                    IpAddr::V6(Ipv6Addr::LOCALHOST),
+                    IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                )
+            } else {
+                (
+                    IPPROTO_ICMP,
+                    IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                )
+            };
+
+        let protocol = args.get("protocol", protocol as usize);
+        let size = args.get("size", 56);
+        let port = args.get("port", 9);
+
+        // Addresses of another family than the domain are not going to work
+        let mut destination = args.get("address", destination);
+        if destination.is_ipv4() != unspecified.is_ipv4() {
+            let default = match unspecified {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            };
+            warn!("Address {destination} mismatches domain, using {default}");
+            destination = default;
+        }
+
+        let source = Some(args.get("source", unspecified))
+            .filter(|source| !source.is_unspecified())
+            .filter(|source| {
+                let matches = source.is_ipv4() == unspecified.is_ipv4();
+                if !matches {
+                    warn!("Source {source} mismatches domain, not spoofing");
+                }
+                matches
+            });
+
+        Self {
+            domain,
+            protocol,
+            destination,
+            source,
+            port,
+            size,
+            sockfd: 0,
+            datagram: false,
+            seq_no: Cell::new(0),
+        }
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        (0..self.size).map(|_| thread_rng().r#gen()).collect()
+    }
+
+    fn is_echo(&self) -> bool {
+        match self.destination {
+            IpAddr::V4(_) => self.protocol == IPPROTO_ICMP as usize,
+            IpAddr::V6(_) => self.protocol == IPPROTO_ICMPV6 as usize,
+        }
+    }
+
+    /// Packet with the IP header, which is stripped off later if the kernel
+    /// builds it.
+    fn packet(&self) -> Vec<u8> {
+        let src = self.source.unwrap_or(match self.destination {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        });
+        let payload = self.payload();
+
+        if self.is_echo() {
+            let seq_no = self.seq_no.get().wrapping_add(1);
+            self.seq_no.set(seq_no);
+
+            echo_request(
+                src.into(),
+                self.destination.into(),
+                std::process::id() as u16,
+                seq_no,
+                &payload,
+            )
+        } else {
+            let repr = IpRepr::new(
+                src.into(),
+                self.destination.into(),
+                IpProtocol::from(self.protocol as u8),
+                self.size,
+                64,
+            );
+            ip_packet(repr, |buf| buf.copy_from_slice(&payload))
+        }
+    }
+}
+
+impl Drop for SendtoCall {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = syscall!(Sysno::close, self.sockfd);
+        }
+    }
+}
+
+impl SysCaller for SendtoCall {
+    fn init(&mut self) -> Result<usize, Errno> {
+        let raw = unsafe {
+            syscall!(
+                Sysno::socket,
+                self.domain,
+                SOCK_RAW | libc::SOCK_NONBLOCK,
+                self.protocol
+            )
+        };
+
+        self.sockfd = match raw {
+            Ok(sockfd) => sockfd,
+            Err(Errno::EPERM) if self.source.is_none() => {
+                warn!("No raw sockets allowed, sending via UDP instead");
+                self.datagram = true;
+                unsafe {
+                    syscall!(
+                        Sysno::socket,
+                        self.domain,
+                        SOCK_DGRAM | libc::SOCK_NONBLOCK,
+                        0
+                    )?
+                }
+            }
+            Err(e) => return Err(e),
+        };
+
+        if self.source.is_some() {
+            let (level, option) = match self.destination {
+                IpAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_HDRINCL),
+                IpAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_HDRINCL),
+            };
+            let on: libc::c_int = 1;
+
+            unsafe {
+                syscall!(
+                    Sysno::setsockopt,
+                    self.sockfd,
+                    level,
+                    option,
+                    &on as *const libc::c_int as usize,
+                    mem::size_of::<libc::c_int>()
+                )?
+            };
+        }
+
+        Ok(self.sockfd)
+    }
+
+    fn call(&self) -> Result<usize, Errno> {
+        let packet = if self.datagram {
+            self.payload()
+        } else {
+            self.packet()
+        };
+
+        // Without a spoofed source the kernel builds the IP header, and fills
+        // in the ICMPv6 checksum as well
+        let header_len = match (self.datagram, self.source, self.destination) {
+            (true, _, _) | (false, Some(_), _) => 0,
+            (false, None, IpAddr::V4(_)) => IPV4_HEADER_LEN,
+            (false, None, IpAddr::V6(_)) => IPV6_HEADER_LEN,
+        };
+        let data = &packet[header_len..];
+        let port = if self.datagram { self.port.to_be() } else { 0 };
+
+        let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let addrlen = match self.destination {
+            IpAddr::V4(ip) => {
+                let sin = libc::sockaddr_in {
+                    sin_family: AF_INET as u16,
+                    sin_port: port,
+                    sin_addr: libc::in_addr {
+                        s_addr: u32::from_ne_bytes(ip.octets()),
+                    },
+                    sin_zero: Default::default(),
+                };
+                unsafe {
+                    *(&mut addr as *mut _ as *mut libc::sockaddr_in) = sin
+                };
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            IpAddr::V6(ip) => {
+                let sin6 = libc::sockaddr_in6 {
+                    sin6_family: AF_INET6 as u16,
+                    sin6_port: port,
+                    sin6_flowinfo: 0,
+                    sin6_addr: libc::in6_addr {
+                        s6_addr: ip.octets(),
+                    },
+                    sin6_scope_id: 0,
+                };
+                unsafe {
+                    *(&mut addr as *mut _ as *mut libc::sockaddr_in6) = sin6
+                };
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+
+        unsafe {
+            syscall!(
+                Sysno::sendto,
+                self.sockfd,
+                data.as_ptr() as usize,
+                data.len(),
+                0,
+                &addr as *const libc::sockaddr_storage as usize,
+                addrlen
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(input: &str) -> ArgsMap {
+        input.parse().unwrap()
+    }
+
+    #[test]
+    fn test_family() {
+        let call = SendtoCall::new(&args("domain=10,address=10.0.0.1"));
+        assert_eq!(call.destination, IpAddr::V6(Ipv6Addr::LOCALHOST));
+
+        let call = SendtoCall::new(&args("address=::1,source=10.0.0.2"));
+        assert_eq!(call.destination, IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(call.source, Some("10.0.0.2".parse().unwrap()));
+
+        let call = SendtoCall::new(&args("domain=10,source=10.0.0.2"));
+        assert_eq!(call.source, None);
+    }
+
+    #[test]
+    fn test_datagram() {
+        let receiver = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = receiver.local_addr().unwrap().port();
+
+        let mut call = SendtoCall::new(&args(&format!("port={port},size=8")));
+        call.datagram = true;
+        call.sockfd =
+            unsafe { syscall!(Sysno::socket, AF_INET, SOCK_DGRAM, 0).unwrap() };
+
+        assert_eq!(call.call(), Ok(8));
+        let mut buf = [0u8; 16];
+        assert_eq!(receiver.recv(&mut buf).unwrap(), 8);
+    }
+}
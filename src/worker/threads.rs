@@ -0,0 +1,319 @@
+use std::{
+    ffi::CString,
+    fmt::Display,
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{self, Instant},
+};
+
+use core_affinity::CoreId;
+use log::{debug, info, trace, warn};
+use rand::{Rng, seq::SliceRandom, thread_rng};
+use rand_distr::Exp;
+use syscalls::Sysno;
+
+use crate::{
+    BaseConfig, Worker, WorkerError, Workload, WorkloadConfig,
+    worker::syscalls::{SysCaller, SysCallerEnum},
+};
+
+/// Thread churn inside a single process, similar to the processes workload:
+/// threads are spawned and exit following Poisson arrival/departure. Every
+/// thread could get a name and invoke syscalls while alive, to be
+/// attributed to it.
+#[derive(Debug, Clone)]
+pub struct ThreadsWorker {
+    config: BaseConfig,
+    workload: WorkloadConfig,
+}
+
+/// Counters reported periodically by the worker.
+#[derive(Debug, Default)]
+struct Stats {
+    spawned: AtomicU64,
+    spawn_failures: AtomicU64,
+    syscalls: AtomicU64,
+}
+
+impl ThreadsWorker {
+    pub fn new(workload: WorkloadConfig, cpu: CoreId, process: usize) -> Self {
+        ThreadsWorker {
+            config: BaseConfig { cpu, process },
+            workload,
+        }
+    }
+
+    /// Body of a spawned thread: set the name, then invoke syscalls until
+    /// the end of the lifetime.
+    fn run_thread(&self, name: Option<String>, lifetime: u64, stats: &Stats) {
+        let Workload::Threads {
+            syscall_rate,
+            syscall_nr,
+            ref syscall_args,
+            ..
+        } = self.workload.workload
+        else {
+            unreachable!()
+        };
+        let BaseConfig { cpu, process } = self.config;
+
+        if let Some(name) = name {
+            set_name(&name);
+        }
+
+        let start = Instant::now();
+        let lifetime = time::Duration::from_millis(lifetime);
+
+        if syscall_rate > 0.0 {
+            let mut caller =
+                SysCallerEnum::new(Sysno::from(syscall_nr), syscall_args);
+            if let Err(e) = caller.init() {
+                warn!(
+                    "{}-{}: Error initializing syscall, {:?}",
+                    cpu.id, process, e
+                );
+            } else {
+                let exp = Exp::new(syscall_rate).unwrap();
+
+                loop {
+                    let interval: f64 = thread_rng().sample(exp);
+                    let interval = time::Duration::from_nanos(
+                        (interval * 1000000000.0).round() as u64,
+                    );
+
+                    if start.elapsed() + interval >= lifetime {
+                        break;
+                    }
+
+                    thread::sleep(interval);
+                    stats.syscalls.fetch_add(1, Ordering::Relaxed);
+                    if let Err(e) = caller.call() {
+                        trace!("{}-{}: Error: {:?}", cpu.id, process, e);
+                    }
+                }
+            }
+        }
+
+        thread::sleep(lifetime.saturating_sub(start.elapsed()));
+    }
+
+    /// Spawn a thread with a random name from the configured ones and a
+    /// sampled stack size.
+    fn spawn_thread<'scope>(
+        &'scope self,
+        scope: &'scope thread::Scope<'scope, '_>,
+        id: u64,
+        lifetime: u64,
+        stats: &'scope Stats,
+    ) -> io::Result<()> {
+        let Workload::Threads {
+            ref names,
+            stack_size,
+            ..
+        } = self.workload.workload
+        else {
+            unreachable!()
+        };
+
+        let name = names
+            .choose(&mut thread_rng())
+            .map(|name| name.replace("{id}", &id.to_string()));
+
+        thread::Builder::new()
+            .stack_size(stack_size.sample() as usize)
+            .spawn_scoped(scope, move || self.run_thread(name, lifetime, stats))
+            .map(|_| ())
+    }
+}
+
+/// Set the name of the current thread, the kernel takes only first 15 bytes.
+fn set_name(name: &str) {
+    let name: Vec<u8> = name.bytes().take(15).collect();
+    let Ok(name) = CString::new(name) else {
+        return;
+    };
+
+    unsafe { libc::prctl(libc::PR_SET_NAME, name.as_ptr()) };
+}
+
+impl Worker for ThreadsWorker {
+    fn run_payload(&self) -> Result<(), WorkerError> {
+        info!("{self}");
+
+        let Workload::Threads {
+            arrival_rate,
+            departure_rate,
+            syscall_rate,
+            syscall_nr,
+            ref syscall_args,
+            ..
+        } = self.workload.workload
+        else {
+            unreachable!()
+        };
+
+        // Every thread initializes its own caller, which doesn't work for
+        // those sharing state across the process
+        let syscall = Sysno::from(syscall_nr);
+        if syscall_rate > 0.0
+            && SysCallerEnum::new(syscall, syscall_args).is_exclusive()
+        {
+            return Err(WorkerError::InternalWithMessage(format!(
+                "{syscall} with the configured arguments keeps process \
+                 wide state, cannot be invoked from multiple threads"
+            )));
+        }
+
+        let stats = Stats::default();
+        let mut start = Instant::now();
+
+        thread::scope(|s| {
+            for id in 0u64.. {
+                if start.elapsed().as_secs() > 10 {
+                    info!(
+                        "{}-{}: Spawned {}, spawn failures {}, syscalls {}",
+                        self.config.cpu.id,
+                        self.config.process,
+                        stats.spawned.load(Ordering::Relaxed),
+                        stats.spawn_failures.load(Ordering::Relaxed),
+                        stats.syscalls.load(Ordering::Relaxed),
+                    );
+                    start = Instant::now();
+                }
+
+                let lifetime: f64 =
+                    thread_rng().sample(Exp::new(departure_rate).unwrap());
+                let lifetime = (lifetime * 1000.0).round() as u64;
+
+                match self.spawn_thread(s, id, lifetime, &stats) {
+                    Ok(_) => {
+                        stats.spawned.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        stats.spawn_failures.fetch_add(1, Ordering::Relaxed);
+                        warn!(
+                            "{}-{}: Failed to spawn a thread, {}",
+                            self.config.cpu.id, self.config.process, e
+                        );
+                    }
+                }
+
+                let interval: f64 =
+                    thread_rng().sample(Exp::new(arrival_rate).unwrap());
+                debug!(
+                    "{}-{}: Interval {}, lifetime {}",
+                    self.config.cpu.id, self.config.process, interval, lifetime
+                );
+                thread::sleep(time::Duration::from_millis(
+                    (interval * 1000.0).round() as u64,
+                ));
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl Display for ThreadsWorker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Config, File, FileFormat};
+    use std::fs;
+
+    fn worker(extra: &str) -> ThreadsWorker {
+        let input = format!(
+            r#"
+            restart_interval = 10
+
+            [workload]
+            type = "threads"
+            arrival_rate = 10.0
+            departure_rate = 10.0
+            {extra}
+            "#
+        );
+
+        let config = Config::builder()
+            .add_source(File::from_str(&input, FileFormat::Toml))
+            .build()
+            .unwrap()
+            .try_deserialize::<WorkloadConfig>()
+            .unwrap();
+
+        ThreadsWorker::new(config, CoreId { id: 0 }, 0)
+    }
+
+    /// Size of the memory mapping the stack of the thread lives in. The
+    /// stack pointer of a blocked thread is the second to last field of its
+    /// syscall file.
+    fn stack_size(tid: &str) -> u64 {
+        let syscall =
+            fs::read_to_string(format!("/proc/self/task/{tid}/syscall"))
+                .unwrap();
+        let fields: Vec<_> = syscall.split_whitespace().collect();
+        let hex = |value: &str| {
+            u64::from_str_radix(value.trim_start_matches("0x"), 16).unwrap()
+        };
+        let sp = hex(fields[fields.len() - 2]);
+
+        fs::read_to_string("/proc/self/maps")
+            .unwrap()
+            .lines()
+            .find_map(|line| {
+                let (start, end) =
+                    line.split_whitespace().next()?.split_once('-')?;
+                let (start, end) = (hex(start), hex(end));
+                (start..end).contains(&sp).then_some(end - start)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_spawn_thread() {
+        // Way above the default, so that no cached stack is reused
+        let worker = worker(
+            r#"
+            names = ["test-{id}"]
+            stack_size = { distribution = "constant", value = 16777216 }
+            "#,
+        );
+        let stats = Stats::default();
+
+        thread::scope(|s| {
+            worker.spawn_thread(s, 7, 500, &stats).unwrap();
+            thread::sleep(time::Duration::from_millis(100));
+
+            let tid = fs::read_dir("/proc/self/task")
+                .unwrap()
+                .map(|task| task.unwrap().file_name().into_string().unwrap())
+                .find(|tid| {
+                    fs::read_to_string(format!("/proc/self/task/{tid}/comm"))
+                        .is_ok_and(|comm| comm.trim_end() == "test-7")
+                })
+                .expect("no thread with the configured name");
+
+            assert!(stack_size(&tid) >= 16 * 1024 * 1024);
+        });
+    }
+
+    #[test]
+    fn test_exclusive() {
+        let worker = worker(&format!(
+            r#"
+            syscall_rate = 1.0
+            syscall_nr = {}
+            syscall_args = "container=true"
+            "#,
+            Sysno::unshare.id()
+        ));
+
+        assert!(worker.run_payload().is_err());
+    }
+}